@@ -22,9 +22,20 @@
 //! Dynamically typed value.
 
 use capability::FromClientHook;
+use orphan::{Orphan, Orphanage};
 use private::capability::{ClientHook, PipelineHook, PipelineOp};
 use private::layout::{PointerReader, PointerBuilder};
 use traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder};
+use traits;
+use {Error, MessageSize, Result};
+
+#[derive(Copy)]
+pub struct Owned;
+
+impl <'a> traits::Owned<'a> for Owned {
+    type Reader = Reader<'a>;
+    type Builder = Builder<'a>;
+}
 
 #[derive(Copy)]
 pub struct Reader<'a> {
@@ -43,29 +54,61 @@ impl <'a> Reader<'a> {
     }
 
     #[inline]
-    pub fn get_as<T : FromPointerReader<'a>>(&self) -> T {
+    pub fn get_as<T : FromPointerReader<'a>>(&self) -> Result<T> {
         FromPointerReader::get_from_pointer(&self.reader)
     }
 
+    /// Deeply compares the values of two pointers, which may come from different messages.
+    /// Far pointers are followed, and trailing zero data words and trailing null pointers are
+    /// treated as absent, so a struct written with an older schema can equal one written with a
+    /// newer schema. Text and data compare bytewise. Returns an error if either value contains a
+    /// capability.
+    pub fn equals(&self, other : &Reader) -> Result<bool> {
+        self.reader.equals(&other.reader)
+    }
+
+    /// Computes a hash of this value that is stable across messages and processes, and that
+    /// agrees with `equals()`.
+    pub fn hash(&self) -> Result<u64> {
+        self.reader.hash()
+    }
+
+    pub fn total_size(&self) -> Result<MessageSize> {
+        self.reader.total_size()
+    }
+
     pub fn get_as_capability<T : FromClientHook>(&self) -> T {
         FromClientHook::new(self.reader.get_capability())
     }
 
     //# Used by RPC system to implement pipelining. Applications
     //# generally shouldn't use this directly.
-    pub fn get_pipelined_cap(&self, ops : &[PipelineOp]) -> Box<ClientHook+Send> {
+    pub fn get_pipelined_cap(&self, ops : &[PipelineOp]) -> Result<Box<ClientHook+Send>> {
         let mut pointer = self.reader;
 
         for op in ops.iter() {
             match op {
                 &PipelineOp::Noop =>  { }
                 &PipelineOp::GetPointerField(idx) => {
-                    pointer = pointer.get_struct(::std::ptr::null()).get_pointer_field(idx as usize)
+                    pointer = try!(pointer.get_struct(::std::ptr::null())).get_pointer_field(idx as usize)
                 }
             }
         }
 
-        pointer.get_capability()
+        Ok(pointer.get_capability())
+    }
+}
+
+// An orphan's tag is not in the message, so a reader or builder of the orphan's pointer must not
+// outlive the orphan. That can't be expressed for the values below, so orphans can't be
+// accessed as any_pointer.
+
+impl <'a> FromPointerReader<'a> for Reader<'a> {
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a>> {
+        if reader.is_orphan() {
+            return Err(Error::new_decode_error("An orphan cannot be read as an AnyPointer.", None));
+        }
+        Ok(Reader::new(*reader))
     }
 }
 
@@ -79,20 +122,38 @@ impl <'a> Builder<'a> {
         Builder { builder : builder }
     }
 
-    pub fn get_as<T : FromPointerBuilder<'a>>(self) -> T {
+    pub fn get_as<T : FromPointerBuilder<'a>>(self) -> Result<T> {
         FromPointerBuilder::get_from_pointer(self.builder)
     }
 
-    pub fn init_as<T : FromPointerBuilder<'a>>(self) -> T {
+    pub fn init_as<T : FromPointerBuilder<'a>>(self) -> Result<T> {
         FromPointerBuilder::init_pointer(self.builder, 0)
     }
 
-    pub fn init_as_sized<T : FromPointerBuilder<'a>>(self, size : u32) -> T {
+    pub fn init_as_sized<T : FromPointerBuilder<'a>>(self, size : u32) -> Result<T> {
         FromPointerBuilder::init_pointer(self.builder, size)
     }
 
-    pub fn set_as<To, From : SetPointerBuilder<To>>(self, value : From) {
-        SetPointerBuilder::<To>::set_pointer_builder(self.builder, value);
+    pub fn set_as<To, From : SetPointerBuilder<To>>(self, value : From) -> Result<()> {
+        SetPointerBuilder::<To>::set_pointer_builder(self.builder, value)
+    }
+
+    /// Sets this pointer to a deep copy of `other`, which may belong to a different message.
+    pub fn set_as_copy_of(self, other : Reader) -> Result<()> {
+        self.builder.copy_from(other.reader)
+    }
+
+    /// Returns an orphanage for the message that contains this pointer.
+    pub fn get_orphanage(&self) -> Orphanage<'a> {
+        unsafe { Orphanage::new(self.builder.get_arena()) }
+    }
+
+    pub fn adopt<T>(self, orphan : Orphan<'a, T>) -> Result<()> {
+        self.builder.adopt(orphan.into_internal())
+    }
+
+    pub fn disown<T>(self) -> Result<Orphan<'a, T>> {
+        Ok(Orphan::new(try!(self.builder.disown())))
     }
 
     // XXX value should be a user client.
@@ -111,6 +172,22 @@ impl <'a> Builder<'a> {
     }
 }
 
+impl <'a> FromPointerBuilder<'a> for Builder<'a> {
+    fn init_pointer(builder : PointerBuilder<'a>, _size : u32) -> Result<Builder<'a>> {
+        if builder.is_orphan() {
+            return Err(Error::new_decode_error("An orphan cannot be built as an AnyPointer.", None));
+        }
+        builder.clear();
+        Ok(Builder::new(builder))
+    }
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a>> {
+        if builder.is_orphan() {
+            return Err(Error::new_decode_error("An orphan cannot be built as an AnyPointer.", None));
+        }
+        Ok(Builder::new(builder))
+    }
+}
+
 pub struct Pipeline {
     hook : Box<PipelineHook+Send>,
     ops : Vec<PipelineOp>,
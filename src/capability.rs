@@ -24,12 +24,13 @@
 //! Roughly corresponds to capability.h in the C++ implementation.
 
 use any_pointer;
+use Result;
 use traits::{FromPointerReader, FromPointerBuilder};
 use private::capability::{CallContextHook, ClientHook, RequestHook, ResponseHook};
 
 pub struct ResultFuture<Results, Pipeline> {
     pub answer_port : ::std::sync::mpsc::Receiver<Box<ResponseHook+Send>>,
-    pub answer_result : Result<Box<ResponseHook+Send>, ()>,
+    pub answer_result : ::std::result::Result<Box<ResponseHook+Send>, ()>,
     pub pipeline : Pipeline,
 }
 
@@ -62,10 +63,10 @@ impl <Params, Results> CallContext<Params, Results> {
 impl <'a, Params : FromPointerReader<'a>, Results : FromPointerBuilder<'a>>
 CallContext<Params, Results> {
     // XXX this 'b lifetime should be 'a.
-    pub fn get<'b>(&'b mut self) -> (Params, Results) {
+    pub fn get<'b>(&'b mut self) -> Result<(Params, Results)> {
         let tmp : &'a mut Box<CallContextHook+Send> = unsafe { ::std::mem::transmute(& mut self.hook)};
         let (any_params, any_results) = tmp.get();
-        (any_params.get_as(), any_results.get_as())
+        Ok((try!(any_params.get_as()), try!(any_results.get_as())))
     }
 }
 
@@ -22,6 +22,7 @@
 //! Sequence of bytes.
 
 use private::layout::{PointerBuilder, PointerReader};
+use Result;
 
 pub type Reader<'a> = &'a [u8];
 
@@ -29,8 +30,16 @@ pub fn new_reader<'a>(p : *const u8, len : u32) -> Reader<'a> {
     unsafe { ::std::slice::from_raw_parts(p, len as usize) }
 }
 
+#[derive(Copy)]
+pub struct Owned;
+
+impl <'a> ::traits::Owned<'a> for Owned {
+    type Reader = Reader<'a>;
+    type Builder = Builder<'a>;
+}
+
 impl <'a> ::traits::FromPointerReader<'a> for Reader<'a> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a> {
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a>> {
         reader.get_data(::std::ptr::null(), 0)
     }
 }
@@ -42,17 +51,17 @@ pub fn new_builder<'a>(p : *mut u8, len : u32) -> Builder<'a> {
 }
 
 impl <'a> ::traits::FromPointerBuilder<'a> for Builder<'a> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a> {
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a>> {
         builder.init_data(size)
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a> {
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a>> {
         builder.get_data(::std::ptr::null(), 0)
     }
 }
 
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
-    fn set_pointer_builder<'b>(pointer : PointerBuilder<'b>, value : Reader<'a>) {
-        pointer.set_data(value);
+    fn set_pointer_builder<'b>(pointer : PointerBuilder<'b>, value : Reader<'a>) -> Result<()> {
+        pointer.set_data(value)
     }
 }
 
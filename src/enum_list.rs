@@ -22,8 +22,18 @@
 //! List of enums.
 
 use traits::{FromPointerReader, FromPointerBuilder, ToU16};
+use traits;
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder,
                       TwoBytes, PrimitiveElement};
+use Result;
+
+#[derive(Copy)]
+pub struct Owned<T>;
+
+impl <'a, T : ::std::num::FromPrimitive> traits::Owned<'a> for Owned<T> {
+    type Reader = Reader<'a, T>;
+    type Builder = Builder<'a, T>;
+}
 
 #[derive(Copy)]
 pub struct Reader<'a, T> {
@@ -40,8 +50,8 @@ impl <'a, T : ::std::num::FromPrimitive> Reader<'a, T> {
 }
 
 impl <'a, T : ::std::num::FromPrimitive> FromPointerReader<'a> for Reader<'a, T> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a, T> {
-        Reader { reader : reader.get_list(TwoBytes, ::std::ptr::null()) }
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
+        Ok(Reader { reader : try!(reader.get_list(TwoBytes, ::std::ptr::null())) })
     }
 }
 
@@ -71,11 +81,11 @@ impl <'a, T : ToU16 + ::std::num::FromPrimitive> Builder<'a, T> {
 }
 
 impl <'a, T : ::std::num::FromPrimitive> FromPointerBuilder<'a> for Builder<'a, T> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a, T> {
-        Builder { builder : builder.init_list(TwoBytes, size) }
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a, T>> {
+        Ok(Builder { builder : try!(builder.init_list(TwoBytes, size)) })
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a, T> {
-        Builder { builder : builder.get_list(TwoBytes, ::std::ptr::null()) }
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a, T>> {
+        Ok(Builder { builder : try!(builder.get_list(TwoBytes, ::std::ptr::null())) })
     }
 }
 
@@ -88,8 +98,9 @@ impl <'a, T : ToU16 + ::std::num::FromPrimitive>  Builder<'a, T> {
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a, T>) {
-        pointer.set_list(&value.reader);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a, T>) -> Result<()> {
+        pointer.set_list(&value.reader)
     }
 }
 
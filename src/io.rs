@@ -21,24 +21,40 @@
 
 //! Input / output.
 
-use std::old_io::{Reader, Writer, IoResult};
+use std::io::{Read, Write, BufRead, Error, ErrorKind, Result};
 
-pub fn read_at_least<R : Reader>(reader : &mut R,
-                                 buf: &mut [u8],
-                                 min_bytes : usize) -> IoResult<usize> {
+/// Reads into `buf` until at least `min_bytes` have been read, and returns the number of bytes
+/// read. Returns fewer than `min_bytes` only if the input ends first.
+pub fn read_at_least<R : Read>(reader : &mut R,
+                               buf: &mut [u8],
+                               min_bytes : usize) -> Result<usize> {
     let mut pos = 0;
     let buf_len = buf.len();
     while pos < min_bytes {
         let buf1 = &mut buf[pos .. buf_len];
         let n = try!(reader.read(buf1));
+        if n == 0 { break; }
         pos += n;
     }
     return Ok(pos);
 }
 
-pub trait BufferedInputStream : Reader {
-    fn skip(&mut self, bytes : usize) -> IoResult<()>;
-    unsafe fn get_read_buffer(&mut self) -> IoResult<(*const u8, *const u8)>;
+/// Fills `buf` completely. Unlike `read_at_least()`, treats the input ending first as an error.
+pub fn read_exact<R : Read>(reader : &mut R, buf : &mut [u8]) -> Result<()> {
+    let buf_len = buf.len();
+    if try!(read_at_least(reader, buf, buf_len)) < buf_len {
+        return Err(premature_end_of_input());
+    }
+    Ok(())
+}
+
+fn premature_end_of_input() -> Error {
+    Error::new(ErrorKind::Other, "Premature end of input.", None)
+}
+
+pub trait BufferedInputStream : Read {
+    fn skip(&mut self, bytes : usize) -> Result<()>;
+    unsafe fn get_read_buffer(&mut self) -> Result<(*const u8, *const u8)>;
 }
 
 pub struct BufferedInputStreamWrapper<'a, R: 'a> {
@@ -63,9 +79,9 @@ impl <'a, R> BufferedInputStreamWrapper<'a, R> {
     }
 }
 
-impl<'a, R: Reader> BufferedInputStream for BufferedInputStreamWrapper<'a, R> {
+impl<'a, R: Read> BufferedInputStream for BufferedInputStreamWrapper<'a, R> {
 
-   fn skip(&mut self, mut bytes : usize) -> IoResult<()> {
+   fn skip(&mut self, mut bytes : usize) -> Result<()> {
         let available = self.cap - self.pos;
         if bytes <= available {
             self.pos += bytes;
@@ -74,6 +90,7 @@ impl<'a, R: Reader> BufferedInputStream for BufferedInputStreamWrapper<'a, R> {
             if bytes <= self.buf.len() {
                 //# Read the next buffer-full.
                 let n = try!(read_at_least(self.inner, self.buf.as_mut_slice(), bytes));
+                if n < bytes { return Err(premature_end_of_input()); }
                 self.pos = bytes;
                 self.cap = n;
             } else {
@@ -84,7 +101,8 @@ impl<'a, R: Reader> BufferedInputStream for BufferedInputStreamWrapper<'a, R> {
         Ok(())
     }
 
-    unsafe fn get_read_buffer(&mut self) -> IoResult<(*const u8, *const u8)> {
+    // Returns an empty buffer at the end of the input.
+    unsafe fn get_read_buffer(&mut self) -> Result<(*const u8, *const u8)> {
         if self.cap - self.pos == 0 {
             let n = try!(read_at_least(self.inner, self.buf.as_mut_slice(), 1));
             self.cap = n;
@@ -95,8 +113,8 @@ impl<'a, R: Reader> BufferedInputStream for BufferedInputStreamWrapper<'a, R> {
     }
 }
 
-impl<'a, R: Reader> Reader for BufferedInputStreamWrapper<'a, R> {
-    fn read(&mut self, dst: &mut [u8]) -> IoResult<usize> {
+impl<'a, R: Read> Read for BufferedInputStreamWrapper<'a, R> {
+    fn read(&mut self, dst: &mut [u8]) -> Result<usize> {
         let mut num_bytes = dst.len();
         if num_bytes <= self.cap - self.pos {
             //# Serve from the current buffer.
@@ -116,11 +134,13 @@ impl<'a, R: Reader> Reader for BufferedInputStreamWrapper<'a, R> {
             if num_bytes <= self.buf.len() {
                 //# Read the next buffer-full.
                 let n = try!(read_at_least(self.inner, self.buf.as_mut_slice(), num_bytes));
+                // At the end of the input, this is a short read.
+                let from_second_buffer = ::std::cmp::min(n, num_bytes);
                 ::std::slice::bytes::copy_memory(dst1,
-                                                 &self.buf[0 .. num_bytes]);
+                                                 &self.buf[0 .. from_second_buffer]);
                 self.cap = n;
-                self.pos = num_bytes;
-                return Ok(from_first_buffer + num_bytes);
+                self.pos = from_second_buffer;
+                return Ok(from_first_buffer + from_second_buffer);
             } else {
                 //# Forward large read to the underlying stream.
                 self.pos = 0;
@@ -131,6 +151,20 @@ impl<'a, R: Reader> Reader for BufferedInputStreamWrapper<'a, R> {
     }
 }
 
+impl<'a, R: Read> BufRead for BufferedInputStreamWrapper<'a, R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.cap - self.pos == 0 {
+            self.cap = try!(self.inner.read(self.buf.as_mut_slice()));
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos .. self.cap])
+    }
+
+    fn consume(&mut self, amt : usize) {
+        self.pos = ::std::cmp::min(self.pos + amt, self.cap);
+    }
+}
+
 pub struct ArrayInputStream<'a> {
     array : &'a [u8]
 }
@@ -141,8 +175,8 @@ impl <'a> ArrayInputStream<'a> {
     }
 }
 
-impl <'a> Reader for ArrayInputStream<'a> {
-    fn read(&mut self, dst: &mut [u8]) -> Result<usize, ::std::old_io::IoError> {
+impl <'a> Read for ArrayInputStream<'a> {
+    fn read(&mut self, dst: &mut [u8]) -> Result<usize> {
         let n = ::std::cmp::min(dst.len(), self.array.len());
         unsafe { ::std::ptr::copy_nonoverlapping_memory(dst.as_mut_ptr(), self.array.as_ptr(), n) }
         self.array = &self.array[n ..];
@@ -150,23 +184,33 @@ impl <'a> Reader for ArrayInputStream<'a> {
     }
 }
 
+impl <'a> BufRead for ArrayInputStream<'a> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(self.array)
+    }
+
+    fn consume(&mut self, amt : usize) {
+        self.array = &self.array[::std::cmp::min(amt, self.array.len()) ..];
+    }
+}
+
 impl <'a> BufferedInputStream for ArrayInputStream<'a> {
-    fn skip(&mut self, bytes : usize) -> IoResult<()> {
+    fn skip(&mut self, bytes : usize) -> Result<()> {
         assert!(self.array.len() >= bytes,
                 "ArrayInputStream ended prematurely.");
         self.array = &self.array[bytes ..];
         Ok(())
     }
-    unsafe fn get_read_buffer(&mut self) -> IoResult<(*const u8, *const u8)> {
+    unsafe fn get_read_buffer(&mut self) -> Result<(*const u8, *const u8)> {
         let len = self.array.len();
         Ok((self.array.as_ptr() as *const u8,
            self.array.get_unchecked(len) as *const u8))
     }
 }
 
-pub trait BufferedOutputStream : Writer {
+pub trait BufferedOutputStream : Write {
     unsafe fn get_write_buffer(&mut self) -> (*mut u8, *mut u8);
-    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> IoResult<()>;
+    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> Result<()>;
 }
 
 pub struct BufferedOutputStreamWrapper<'a, W:'a> {
@@ -189,7 +233,7 @@ impl <'a, W> BufferedOutputStreamWrapper<'a, W> {
     }
 }
 
-impl<'a, W: Writer> BufferedOutputStream for BufferedOutputStreamWrapper<'a, W> {
+impl<'a, W: Write> BufferedOutputStream for BufferedOutputStreamWrapper<'a, W> {
     #[inline]
     unsafe fn get_write_buffer(&mut self) -> (*mut u8, *mut u8) {
         let len = self.buf.len();
@@ -198,7 +242,7 @@ impl<'a, W: Writer> BufferedOutputStream for BufferedOutputStreamWrapper<'a, W>
     }
 
     #[inline]
-    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> IoResult<()> {
+    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> Result<()> {
         let easy_case = ptr == self.buf.get_unchecked_mut(self.pos) as *mut u8;
         if easy_case {
             self.pos += size;
@@ -212,8 +256,8 @@ impl<'a, W: Writer> BufferedOutputStream for BufferedOutputStreamWrapper<'a, W>
 }
 
 
-impl<'a, W: Writer> Writer for BufferedOutputStreamWrapper<'a, W> {
-    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+impl<'a, W: Write> Write for BufferedOutputStreamWrapper<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let available = self.buf.len() - self.pos;
         let mut size = buf.len();
         if size <= available {
@@ -241,10 +285,10 @@ impl<'a, W: Writer> Writer for BufferedOutputStreamWrapper<'a, W> {
             self.pos = 0;
             try!(self.inner.write_all(buf));
         }
-        return Ok(());
+        return Ok(buf.len());
     }
 
-    fn flush(&mut self) -> IoResult<()> {
+    fn flush(&mut self) -> Result<()> {
         if self.pos > 0 {
             try!(self.inner.write_all(&self.buf[0 .. self.pos]));
             self.pos = 0;
@@ -267,8 +311,8 @@ impl <'a> ArrayOutputStream<'a> {
     }
 }
 
-impl <'a> Writer for ArrayOutputStream<'a> {
-    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+impl <'a> Write for ArrayOutputStream<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
         assert!(buf.len() <= self.array.len() - self.fill_pos,
                 "ArrayOutputStream's backing array was not large enough for the data written.");
         unsafe { ::std::ptr::copy_nonoverlapping_memory(
@@ -276,6 +320,10 @@ impl <'a> Writer for ArrayOutputStream<'a> {
             buf.as_ptr(),
             buf.len());  }
         self.fill_pos += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
         Ok(())
     }
 }
@@ -286,7 +334,7 @@ impl <'a> BufferedOutputStream for ArrayOutputStream<'a> {
         (self.array.get_unchecked_mut(self.fill_pos) as *mut u8,
          self.array.get_unchecked_mut(len) as *mut u8)
     }
-    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> IoResult<()> {
+    unsafe fn write_ptr(&mut self, ptr: *mut u8, size: usize) -> Result<()> {
         let easy_case = ptr == self.array.get_unchecked_mut(self.fill_pos) as *mut u8;
         if easy_case {
             self.fill_pos += size;
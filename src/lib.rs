@@ -34,7 +34,7 @@
 //! [capnp-rpc-rust](https://github.com/dwrensha/capnp-rpc-rust) is an implementation of a
 //! distributed object-capability layer.
 
-#![feature(alloc, core, old_io, unsafe_destructor)]
+#![feature(alloc, core, io, unsafe_destructor)]
 #![allow(raw_pointer_derive)]
 
 #![crate_name="capnp"]
@@ -54,6 +54,7 @@ pub mod private;
 pub mod io;
 pub mod list_list;
 pub mod message;
+pub mod orphan;
 pub mod primitive_list;
 pub mod serialize;
 pub mod serialize_packed;
@@ -61,6 +62,7 @@ pub mod struct_list;
 pub mod text;
 pub mod text_list;
 pub mod traits;
+pub mod validate;
 
 /// Eight bytes of memory with opaque interior.
 ///
@@ -95,6 +97,75 @@ impl Word {
     }
 }
 
+/// Because messages are lazily validated, the return type of any method that reads a pointer field
+/// must be wrapped in a Result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Things that can go wrong when you read a message.
+#[derive(Debug)]
+pub enum Error {
+    /// The message is malformed.
+    Decode { description : &'static str,
+             detail : Option<String> },
+
+    /// An I/O operation on the underlying stream failed.
+    Io(::std::io::Error),
+
+    /// Building the message would exceed a limit, such as one set in `BuilderOptions`, or the
+    /// memory for it could not be allocated.
+    ResourceLimit { description : &'static str,
+                    detail : Option<String> },
+}
+
+impl Error {
+    pub fn new_decode_error(description : &'static str, detail : Option<String>) -> Error {
+        Error::Decode { description : description, detail : detail}
+    }
+
+    pub fn new_resource_limit_error(description : &'static str, detail : Option<String>) -> Error {
+        Error::ResourceLimit { description : description, detail : detail}
+    }
+}
+
+impl ::std::error::FromError<::std::io::Error> for Error {
+    fn from_error(err : ::std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl ::std::fmt::Display for Error {
+    fn fmt(&self, fmt : &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        match *self {
+            Error::Decode { ref description, detail : Some(ref detail) } => {
+                write!(fmt, "{} - {}", description, detail)
+            }
+            Error::Decode { ref description, .. } => write!(fmt, "{}", description),
+            Error::Io(ref io) => io.fmt(fmt),
+            Error::ResourceLimit { ref description, detail : Some(ref detail) } => {
+                write!(fmt, "{} - {}", description, detail)
+            }
+            Error::ResourceLimit { ref description, .. } => write!(fmt, "{}", description),
+        }
+    }
+}
+
+impl ::std::error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Decode { ref description, .. } => description,
+            Error::Io(ref io) => ::std::error::Error::description(io),
+            Error::ResourceLimit { ref description, .. } => description,
+        }
+    }
+    fn cause(&self) -> Option<&::std::error::Error> {
+        match *self {
+            Error::Decode { .. } => None,
+            Error::Io(ref io) => Some(io),
+            Error::ResourceLimit { .. } => None,
+        }
+    }
+}
+
 /// Size of a message. Every generated struct has a method `.total_size()` that returns this.
 #[derive(Copy)]
 pub struct MessageSize {
@@ -22,7 +22,19 @@
 //! List of lists.
 
 use traits::{FromPointerReader, FromPointerBuilder};
+use traits;
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder, Pointer};
+use Result;
+
+/// `T` is the `Owned` marker of the element list.
+pub struct Owned<T>;
+
+impl <T> Copy for Owned<T> {}
+
+impl <'a, T : traits::Owned<'a>> traits::Owned<'a> for Owned<T> {
+    type Reader = Reader<'a, <T as traits::Owned<'a>>::Reader>;
+    type Builder = Builder<'a, <T as traits::Owned<'a>>::Builder>;
+}
 
 #[derive(Copy)]
 pub struct Reader<'a, T> {
@@ -38,13 +50,13 @@ impl <'a, T> Reader<'a, T> {
 }
 
 impl <'a, T : FromPointerReader<'a>> FromPointerReader<'a> for Reader<'a, T> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a, T> {
-        Reader { reader : reader.get_list(Pointer, ::std::ptr::null()) }
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
+        Ok(Reader { reader : try!(reader.get_list(Pointer, ::std::ptr::null())) })
     }
 }
 
 impl <'a, T : FromPointerReader<'a>> Reader<'a, T> {
-    pub fn get(self, index : u32) -> T {
+    pub fn get(self, index : u32) -> Result<T> {
         assert!(index <  self.len());
         FromPointerReader::get_from_pointer(&self.reader.get_pointer_element(index))
     }
@@ -61,10 +73,8 @@ impl <'a, T : FromPointerBuilder<'a>> Builder<'a, T> {
 
     pub fn len(&self) -> u32 { self.builder.len() }
 
-    pub fn init(self, index : u32, size : u32) -> T {
-        let result : T =
-            FromPointerBuilder::init_pointer(self.builder.get_pointer_element(index), size);
-        result
+    pub fn init(self, index : u32, size : u32) -> Result<T> {
+        FromPointerBuilder::init_pointer(self.builder.get_pointer_element(index), size)
     }
 }
 
@@ -75,28 +85,29 @@ impl <'a, T> Builder<'a, T> {
 }
 
 impl <'a, T : FromPointerBuilder<'a>> FromPointerBuilder<'a> for Builder<'a, T> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a, T> {
-        Builder {
-            builder : builder.init_list(Pointer, size)
-        }
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a, T>> {
+        Ok(Builder {
+            builder : try!(builder.init_list(Pointer, size))
+        })
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a, T> {
-        Builder {
-            builder : builder.get_list(Pointer, ::std::ptr::null())
-        }
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a, T>> {
+        Ok(Builder {
+            builder : try!(builder.get_list(Pointer, ::std::ptr::null()))
+        })
     }
 }
 
 impl <'a, T : FromPointerBuilder<'a>> Builder<'a, T> {
-    pub fn get(self, index : u32) -> T {
+    pub fn get(self, index : u32) -> Result<T> {
         assert!(index < self.len());
         FromPointerBuilder::get_from_pointer(self.builder.get_pointer_element(index))
     }
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a, T>) {
-        pointer.set_list(&value.reader);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a, T>) -> Result<()> {
+        pointer.set_list(&value.reader)
     }
 }
 
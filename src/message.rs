@@ -22,26 +22,29 @@
 use std;
 use std::vec::Vec;
 use any_pointer;
+use orphan::Orphanage;
 use private::capability::ClientHook;
 use private::units::*;
-use private::arena::{BuilderArena, ReaderArena, SegmentBuilder, SegmentReader, NumWords, ZeroedWords};
+use private::arena::{BuilderArena, ReaderArena, SegmentBuilder, SegmentReader};
 use private::layout;
-use traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder};
-use Word;
+use serialize::{self, OwnedSpaceMessageReader};
+use traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder, Owned};
+use validate::{PathPattern, Problem};
+use {Error, MessageSize, Result, Word};
 
 #[derive(Copy)]
 pub struct ReaderOptions {
+    /// Limits how many total words of data are allowed to be traversed. Every struct or list
+    /// dereference is charged against this limit, so a message in which many pointers alias the
+    /// same data cannot be used to make the reader do an unbounded amount of work.
     pub traversal_limit_in_words : u64,
-    pub nesting_limit : i32,
 
-    /// If true, malformed messages trigger task failure.
-    /// If false, malformed messages fall back to default values.
-    pub fail_fast : bool,
+    /// Limits how deeply nested a message structure can be.
+    pub nesting_limit : i32,
 }
 
 pub const DEFAULT_READER_OPTIONS : ReaderOptions =
-    ReaderOptions { traversal_limit_in_words : 8 * 1024 * 1024, nesting_limit : 64,
-                    fail_fast : true };
+    ReaderOptions { traversal_limit_in_words : 8 * 1024 * 1024, nesting_limit : 64 };
 
 impl ReaderOptions {
     pub fn new() -> ReaderOptions { DEFAULT_READER_OPTIONS }
@@ -55,11 +58,6 @@ impl ReaderOptions {
         self.traversal_limit_in_words = value;
         return self;
     }
-
-    pub fn fail_fast<'a>(&'a mut self, value : bool) -> &'a mut ReaderOptions {
-        self.fail_fast = value;
-        return self;
-    }
 }
 
 
@@ -71,19 +69,43 @@ pub trait MessageReader {
     fn mut_arena(&mut self) -> &mut ReaderArena;
     fn get_options(&self) -> &ReaderOptions;
 
-    fn get_root_internal(&self) -> any_pointer::Reader {
+    fn get_root_internal(&self) -> Result<any_pointer::Reader> {
         unsafe {
             let segment : *const SegmentReader = &self.arena().segment0;
 
-            let pointer_reader = layout::PointerReader::get_root(
-                segment, (*segment).get_start_ptr(), self.get_options().nesting_limit);
+            let pointer_reader = try!(layout::PointerReader::get_root(
+                segment, (*segment).get_start_ptr(), self.get_options().nesting_limit));
 
-            any_pointer::Reader::new(pointer_reader)
+            Ok(any_pointer::Reader::new(pointer_reader))
         }
     }
 
-    fn get_root<'a, T : FromPointerReader<'a>>(&'a self) -> T {
-        self.get_root_internal().get_as()
+    fn get_root<'a, T : FromPointerReader<'a>>(&'a self) -> Result<T> {
+        try!(self.get_root_internal()).get_as()
+    }
+
+    /// Walks every pointer in the message and returns all of the problems found, rather than
+    /// stopping at the first one. The walk is bounded by the `ReaderOptions`, but does not count
+    /// against the traversal limit used for ordinary reads.
+    fn validate(&self) -> Vec<Problem> {
+        self.validate_with_text(&[])
+    }
+
+    /// Like `validate()`, but also checks that the pointers at `text_paths` are NUL-terminated
+    /// text. For example, `&[&[PathPattern::PointerField(0), PathPattern::AnyListElement]]`
+    /// declares that the root's first pointer field is a `List(Text)`.
+    fn validate_with_text(&self, text_paths : &[&[PathPattern]]) -> Vec<Problem> {
+        let segment : *const SegmentReader = &self.arena().segment0;
+        let options = self.get_options();
+        layout::validate_root(segment, options.traversal_limit_in_words, options.nesting_limit,
+                              text_paths)
+    }
+
+    /// Checks whether this message is in canonical form, i.e. whether it is byte-for-byte what
+    /// `canonicalize()` would produce for its root.
+    fn is_canonical(&self) -> Result<bool> {
+        if self.arena().more_segments.len() > 0 { return Ok(false); }
+        layout::is_canonical_root(&self.arena().segment0, self.get_options().nesting_limit)
     }
 
     fn init_cap_table(&mut self, cap_table : Vec<Option<Box<ClientHook+Send>>>) {
@@ -137,16 +159,20 @@ pub struct BuilderOptions {
     pub first_segment_words : u32,
     pub allocation_strategy : AllocationStrategy,
 
-    /// If true, malformed messages trigger task failure.
-    /// If false, malformed messages fall back to default values.
-    pub fail_fast : bool,
+    /// Limits how many words of segment memory may be allocated from the heap for a single
+    /// message. Attempting to grow a message past this limit results in an error.
+    pub max_total_words : u64,
+
+    /// Limits how many segments may be allocated from the heap for a single message.
+    pub max_segments : u32,
 }
 
 impl BuilderOptions {
     pub fn new() -> BuilderOptions {
         BuilderOptions {first_segment_words : SUGGESTED_FIRST_SEGMENT_WORDS,
                         allocation_strategy : AllocationStrategy::GrowHeuristically,
-                        fail_fast : true }
+                        max_total_words : ::std::u64::MAX,
+                        max_segments : ::std::u32::MAX }
     }
 
     pub fn first_segment_words<'a>(&'a mut self, value : u32) -> &'a mut BuilderOptions {
@@ -159,8 +185,13 @@ impl BuilderOptions {
         return self;
     }
 
-    pub fn fail_fast<'a>(&'a mut self, value : bool) -> &'a mut BuilderOptions {
-        self.fail_fast = value;
+    pub fn max_total_words<'a>(&'a mut self, value : u64) -> &'a mut BuilderOptions {
+        self.max_total_words = value;
+        return self;
+    }
+
+    pub fn max_segments<'a>(&'a mut self, value : u32) -> &'a mut BuilderOptions {
+        self.max_segments = value;
         return self;
     }
 }
@@ -172,38 +203,48 @@ pub trait MessageBuilder {
 
 
     // XXX is there a way to make this private?
-    fn get_root_internal<'a>(&mut self) -> any_pointer::Builder<'a> {
+    fn get_root_internal<'a>(&mut self) -> Result<any_pointer::Builder<'a>> {
         let root_segment = &mut self.mut_arena().segment0 as *mut SegmentBuilder;
 
         if self.arena().segment0.current_size() == 0 {
             match self.mut_arena().segment0.allocate(WORDS_PER_POINTER as u32) {
-                None => {panic!("could not allocate root pointer") }
+                None => {
+                    Err(Error::new_resource_limit_error(
+                        "First segment has no room for the root pointer.", None))
+                }
                 Some(location) => {
                     assert!(location == self.arena().segment0.get_ptr_unchecked(0),
                             "First allocated word of new segment was not at offset 0");
 
-                    any_pointer::Builder::new(layout::PointerBuilder::get_root(root_segment, location))
+                    Ok(any_pointer::Builder::new(layout::PointerBuilder::get_root(root_segment, location)))
 
                 }
             }
         } else {
-            any_pointer::Builder::new(
+            Ok(any_pointer::Builder::new(
                 layout::PointerBuilder::get_root(root_segment,
-                                                 self.arena().segment0.get_ptr_unchecked(0)))
+                                                 self.arena().segment0.get_ptr_unchecked(0))))
         }
 
     }
 
-    fn init_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> T {
-        self.get_root_internal().init_as()
+    fn init_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> Result<T> {
+        try!(self.get_root_internal()).init_as()
+    }
+
+    fn get_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> Result<T> {
+        try!(self.get_root_internal()).get_as()
     }
 
-    fn get_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> T {
-        self.get_root_internal().get_as()
+    fn set_root<To, From : SetPointerBuilder<To>>(&mut self, value : From) -> Result<()> {
+        try!(self.get_root_internal()).set_as(value)
     }
 
-    fn set_root<To, From : SetPointerBuilder<To>>(&mut self, value : From) {
-        self.get_root_internal().set_as(value);
+    fn get_orphanage<'a>(&'a mut self) -> Result<Orphanage<'a>> {
+        // Make sure the root pointer is allocated first, so that it ends up at the start of
+        // the first segment.
+        try!(self.get_root_internal());
+        Ok(unsafe { Orphanage::new(self.mut_arena()) })
     }
 
     fn get_segments_for_output<T, U : FnMut(&[&[Word]]) -> T>(&self, cont : U) -> T {
@@ -215,79 +256,251 @@ pub trait MessageBuilder {
     }
 }
 
-pub struct MallocMessageBuilder {
-    arena : Box<BuilderArena>,
+/// An object that allocates memory for a Cap'n Proto message as it is being built.
+pub trait Allocator {
+    /// Allocates zeroed memory for a new segment, returning a pointer to the start of the segment
+    /// and the length of the segment in words. The segment must be at least `minimum_size` words
+    /// long. An error return causes the operation that needed the space to fail.
+    fn allocate_segment(&mut self, minimum_size : u32) -> Result<(*mut Word, u32)>;
+
+    /// Called once for each segment returned by `allocate_segment()`, when the message that owns
+    /// it is dropped. `words_used` says how much of the segment the message actually wrote to.
+    fn deallocate_segment(&mut self, ptr : *mut Word, word_size : u32, words_used : u32);
 }
 
-impl Drop for MallocMessageBuilder {
-    fn drop(&mut self) { }
+// The largest segment that a far pointer's 29-bit offset can address all of.
+const MAX_SEGMENT_WORDS : u32 = (1 << 29) - 1;
+
+/// Allocates each segment on the heap, sizing and limiting segments according to a
+/// `BuilderOptions`.
+pub struct HeapAllocator {
+    next_size : u32,
+    allocation_strategy : AllocationStrategy,
+    words_remaining : u64,
+    segments_remaining : u32,
 }
 
-unsafe impl Send for MallocMessageBuilder {}
+impl HeapAllocator {
+    pub fn new(options : BuilderOptions) -> HeapAllocator {
+        HeapAllocator { next_size : options.first_segment_words,
+                        allocation_strategy : options.allocation_strategy,
+                        words_remaining : options.max_total_words,
+                        segments_remaining : options.max_segments }
+    }
+}
 
-impl MallocMessageBuilder {
+impl Allocator for HeapAllocator {
+    fn allocate_segment(&mut self, minimum_size : u32) -> Result<(*mut Word, u32)> {
+        if self.segments_remaining == 0 {
+            return Err(Error::new_resource_limit_error(
+                "Message has too many segments. See capnp::BuilderOptions.", None));
+        }
+        if (minimum_size as u64) > self.words_remaining {
+            return Err(Error::new_resource_limit_error(
+                "Message is too large. See capnp::BuilderOptions.", None));
+        }
+
+        // Don't let the growth heuristic push us over the limit when the minimum would fit, or
+        // make a segment bigger than far pointers can address.
+        let preferred_size = ::std::cmp::min(self.next_size, MAX_SEGMENT_WORDS) as u64;
+        let size = ::std::cmp::max(minimum_size as u64,
+                                   ::std::cmp::min(preferred_size, self.words_remaining)) as u32;
+        let ptr : *mut Word = unsafe {
+            ::std::mem::transmute(::std::rt::heap::allocate(BYTES_PER_WORD * size as usize,
+                                                            ::std::mem::min_align_of::<Word>())) };
+        if ptr.is_null() {
+            return Err(Error::new_resource_limit_error("Could not allocate a new segment.", None));
+        }
+        unsafe { ::std::ptr::zero_memory(ptr, size as usize) };
 
-    pub fn new(options : BuilderOptions) -> MallocMessageBuilder {
-        let arena = BuilderArena::new(options.allocation_strategy,
-                                      NumWords(options.first_segment_words),
-                                      options.fail_fast);
+        self.words_remaining -= size as u64;
+        self.segments_remaining -= 1;
 
-        MallocMessageBuilder { arena : arena }
+        match self.allocation_strategy {
+            AllocationStrategy::GrowHeuristically => {
+                self.next_size = ::std::cmp::min(self.next_size.saturating_add(size), MAX_SEGMENT_WORDS);
+            }
+            _ => { }
+        }
+        Ok((ptr, size))
     }
 
-    pub fn new_default() -> MallocMessageBuilder {
-        MallocMessageBuilder::new(BuilderOptions::new())
+    fn deallocate_segment(&mut self, ptr : *mut Word, word_size : u32, _words_used : u32) {
+        self.words_remaining += word_size as u64;
+        self.segments_remaining += 1;
+        unsafe {
+            ::std::rt::heap::deallocate(ptr as *mut u8,
+                                        BYTES_PER_WORD * word_size as usize,
+                                        ::std::mem::min_align_of::<Word>());
+        }
     }
+}
 
+/// Uses a caller-provided buffer of zeroed words for the first segment, and falls back to a
+/// `HeapAllocator` for any further segments. The buffer is zeroed again when the message is
+/// dropped, so that it can be reused for another message.
+pub struct ScratchSpaceHeapAllocator<'a> {
+    scratch_space : &'a mut [Word],
+    scratch_space_allocated : bool,
+    allocator : HeapAllocator,
 }
 
-impl MessageBuilder for MallocMessageBuilder {
-    fn mut_arena(&mut self) -> &mut BuilderArena {
-        &mut *self.arena
+impl <'a> ScratchSpaceHeapAllocator<'a> {
+    pub fn new<'b>(scratch_space : &'b mut [Word], options : BuilderOptions)
+                   -> ScratchSpaceHeapAllocator<'b> {
+        // The first heap-allocated segment is the same size as the scratch space.
+        let mut allocator = HeapAllocator::new(options);
+        allocator.next_size = scratch_space.len() as u32;
+        ScratchSpaceHeapAllocator { scratch_space : scratch_space,
+                                    scratch_space_allocated : false,
+                                    allocator : allocator }
     }
-    fn arena(&self) -> &BuilderArena {
-        & *self.arena
+
+    pub fn new_default<'b>(scratch_space : &'b mut [Word]) -> ScratchSpaceHeapAllocator<'b> {
+        ScratchSpaceHeapAllocator::new(scratch_space, BuilderOptions::new())
     }
 }
 
+impl <'a> Allocator for ScratchSpaceHeapAllocator<'a> {
+    fn allocate_segment(&mut self, minimum_size : u32) -> Result<(*mut Word, u32)> {
+        if !self.scratch_space_allocated && self.scratch_space.len() as u64 >= minimum_size as u64 {
+            self.scratch_space_allocated = true;
+            Ok((self.scratch_space.as_mut_ptr(), self.scratch_space.len() as u32))
+        } else {
+            self.allocator.allocate_segment(minimum_size)
+        }
+    }
 
-pub struct ScratchSpaceMallocMessageBuilder<'a> {
-    arena : Box<BuilderArena>,
-    scratch_space : &'a mut [Word],
+    fn deallocate_segment(&mut self, ptr : *mut Word, word_size : u32, words_used : u32) {
+        if ptr == self.scratch_space.as_mut_ptr() {
+            unsafe { ::std::ptr::zero_memory(ptr, words_used as usize); }
+            self.scratch_space_allocated = false;
+        } else {
+            self.allocator.deallocate_segment(ptr, word_size, words_used);
+        }
+    }
 }
 
+/// A message builder whose segments come from an `Allocator`. By default segments are allocated
+/// on the heap.
+pub struct MallocMessageBuilder<A : Allocator = HeapAllocator> {
+    arena : Box<BuilderArena>,
+
+    // Boxed so that the arena's pointer to it stays valid when the builder is moved.
+    allocator : Box<A>,
+}
 
-// TODO: figure out why rust thinks this is unsafe.
 #[unsafe_destructor]
-impl <'a> Drop for ScratchSpaceMallocMessageBuilder<'a> {
+impl <A : Allocator> Drop for MallocMessageBuilder<A> {
     fn drop(&mut self) {
-        let ptr = self.scratch_space.as_mut_ptr();
-        self.get_segments_for_output(|segments| {
-                unsafe {
-                    std::ptr::zero_memory(ptr, segments[0].len());
-                }
-            });
+        self.arena.deallocate_segments();
     }
 }
 
+unsafe impl <A : Allocator + Send> Send for MallocMessageBuilder<A> {}
 
-impl <'a> ScratchSpaceMallocMessageBuilder<'a> {
+impl MallocMessageBuilder<HeapAllocator> {
 
-    pub fn new<'b>(scratch_space : &'b mut [Word], options : BuilderOptions)
-               -> ScratchSpaceMallocMessageBuilder<'b> {
-        let arena = BuilderArena::new(options.allocation_strategy, ZeroedWords(scratch_space),
-                                      options.fail_fast);
+    /// Returns an error if the first segment can't be allocated, for example because `options`
+    /// doesn't allow any segments.
+    pub fn new(options : BuilderOptions) -> Result<MallocMessageBuilder<HeapAllocator>> {
+        MallocMessageBuilder::new_with_allocator(HeapAllocator::new(options))
+    }
 
-        ScratchSpaceMallocMessageBuilder { arena : arena, scratch_space : scratch_space }
+    /// Like `new()` with default options, which set no limits. Panics if the heap can't supply
+    /// the first segment.
+    pub fn new_default() -> MallocMessageBuilder<HeapAllocator> {
+        MallocMessageBuilder::new_unlimited(BuilderOptions::new())
     }
 
-    pub fn new_default<'b>(scratch_space : &'b mut [Word]) -> ScratchSpaceMallocMessageBuilder<'b> {
-        ScratchSpaceMallocMessageBuilder::new(scratch_space, BuilderOptions::new())
+    /// Creates a builder whose first segment is just big enough to hold an object of the given
+    /// size as the root of the message, so that copying such an object into the message needs
+    /// no further segments. Objects that are too large for one segment get a first segment of
+    /// the default size instead. Like `new_default()`, panics if the heap can't supply the first
+    /// segment.
+    pub fn with_size_hint(size : MessageSize) -> MallocMessageBuilder<HeapAllocator> {
+        let words = size.word_count + WORDS_PER_POINTER as u64;
+        let mut options = BuilderOptions::new();
+        if words < (1 << 29) {
+            options.first_segment_words(words as u32);
+        }
+        let mut result = MallocMessageBuilder::new_unlimited(options);
+        result.arena.cap_table.reserve(size.cap_count as usize);
+        result
+    }
+
+    // Only for options without limits, where the first allocation can fail only if the heap is
+    // exhausted.
+    fn new_unlimited(options : BuilderOptions) -> MallocMessageBuilder<HeapAllocator> {
+        match MallocMessageBuilder::new(options) {
+            Ok(message) => message,
+            Err(e) => panic!("could not allocate first segment: {}", e),
+        }
+    }
+
+}
+
+impl <A : Allocator> MallocMessageBuilder<A> {
+
+    /// Returns an error if `allocator` fails to allocate the first segment.
+    pub fn new_with_allocator(allocator : A) -> Result<MallocMessageBuilder<A>> {
+        let mut allocator = Box::new(allocator);
+        let arena = try!(BuilderArena::new(MallocMessageBuilder::allocator_ptr(&mut allocator), 0));
+        Ok(MallocMessageBuilder { arena : arena, allocator : allocator })
+    }
+
+    fn allocator_ptr(allocator : &mut Box<A>) -> *mut Allocator {
+        // The arena does not know the lifetime of the allocator, but it will only use it
+        // while this builder is alive.
+        unsafe { ::std::mem::transmute(&mut **allocator as &mut Allocator) }
+    }
+
+    pub fn get_allocator<'a>(&'a self) -> &'a A {
+        &*self.allocator
+    }
+
+    /// Discards the message built so far, keeping its segments around for the next message.
+    /// Only the parts of the segments that were actually used need to be zeroed, which makes
+    /// this much cheaper than constructing a new builder.
+    pub fn reset(&mut self) {
+        self.arena.reset();
+    }
+
+    /// Copies the message into a fresh arena whose first segment is big enough to hold all of
+    /// it, and frees the old segments. This reclaims the space left behind by objects that were
+    /// overwritten, upgraded, or disowned. Returns how many words were reclaimed.
+    pub fn compact(&mut self) -> Result<u64> {
+        try!(self.get_root_internal());
+        let old_segment = &mut self.arena.segment0 as *mut SegmentBuilder;
+        let old_root = unsafe {
+            layout::PointerBuilder::get_root(old_segment, (*old_segment).get_ptr_unchecked(0)).as_reader()
+        };
+        let size = try!(old_root.total_size()).word_count + WORDS_PER_POINTER as u64;
+        if size >= (1 << 29) {
+            return Err(Error::new_decode_error("Message is too large to compact.", None));
+        }
+
+        let mut arena = try!(BuilderArena::new(MallocMessageBuilder::allocator_ptr(&mut self.allocator),
+                                               size as u32));
+        let copied = {
+            let segment = &mut arena.segment0 as *mut SegmentBuilder;
+            let location = unsafe { (*segment).allocate(WORDS_PER_POINTER as u32).unwrap() };
+            layout::PointerBuilder::get_root(segment, location).copy_from(old_root)
+        };
+        if let Err(e) = copied {
+            arena.deallocate_segments();
+            return Err(e);
+        }
+
+        let words_before = self.arena.words_used();
+        self.arena.deallocate_segments();
+        self.arena = arena;
+        Ok(words_before - self.arena.words_used())
     }
 
 }
 
-impl <'b> MessageBuilder for ScratchSpaceMallocMessageBuilder<'b> {
+impl <A : Allocator> MessageBuilder for MallocMessageBuilder<A> {
     fn mut_arena(&mut self) -> &mut BuilderArena {
         &mut *self.arena
     }
@@ -295,3 +508,187 @@ impl <'b> MessageBuilder for ScratchSpaceMallocMessageBuilder<'b> {
         & *self.arena
     }
 }
+
+/// Hands out heap-allocated message builders and takes them back once they are no longer needed,
+/// so that their segments can be reused for later messages.
+pub struct MallocMessageBuilderPool {
+    options : BuilderOptions,
+    max_pooled : usize,
+    builders : Vec<MallocMessageBuilder>,
+}
+
+impl MallocMessageBuilderPool {
+    /// Creates a pool that holds on to at most `max_pooled` idle builders. New builders are
+    /// created with `options`.
+    pub fn new(options : BuilderOptions, max_pooled : usize) -> MallocMessageBuilderPool {
+        MallocMessageBuilderPool { options : options, max_pooled : max_pooled, builders : Vec::new() }
+    }
+
+    /// Returns an empty builder, reusing one from the pool if one is available.
+    pub fn get(&mut self) -> Result<MallocMessageBuilder> {
+        match self.builders.pop() {
+            Some(builder) => Ok(builder),
+            None => MallocMessageBuilder::new(self.options),
+        }
+    }
+
+    /// Resets `builder` and returns it to the pool. If the pool is already full, the builder is
+    /// dropped instead.
+    pub fn put(&mut self, mut builder : MallocMessageBuilder) {
+        if self.builders.len() < self.max_pooled {
+            builder.reset();
+            self.builders.push(builder);
+        }
+    }
+}
+
+/// A message builder whose first segment lives in a caller-provided buffer. This is a
+/// `MallocMessageBuilder` with a `ScratchSpaceHeapAllocator`. It is a separate type, rather than
+/// an alias, so that its constructors don't clash with `MallocMessageBuilder::new()` and
+/// `MallocMessageBuilder::new_default()`.
+pub struct ScratchSpaceMallocMessageBuilder<'a> {
+    builder : MallocMessageBuilder<ScratchSpaceHeapAllocator<'a>>,
+}
+
+impl <'a> ScratchSpaceMallocMessageBuilder<'a> {
+    /// Returns an error if the first segment can't be allocated. That only happens when the
+    /// scratch space is empty and `options` doesn't allow any heap segments.
+    pub fn new<'b>(scratch_space : &'b mut [Word], options : BuilderOptions)
+                   -> Result<ScratchSpaceMallocMessageBuilder<'b>> {
+        let builder = try!(MallocMessageBuilder::new_with_allocator(
+            ScratchSpaceHeapAllocator::new(scratch_space, options)));
+        Ok(ScratchSpaceMallocMessageBuilder { builder : builder })
+    }
+
+    /// Like `new()` with default options. Panics if the scratch space is empty and the heap can't
+    /// supply the first segment.
+    pub fn new_default<'b>(scratch_space : &'b mut [Word]) -> ScratchSpaceMallocMessageBuilder<'b> {
+        match ScratchSpaceMallocMessageBuilder::new(scratch_space, BuilderOptions::new()) {
+            Ok(message) => message,
+            Err(e) => panic!("could not allocate first segment: {}", e),
+        }
+    }
+}
+
+impl <'a> MessageBuilder for ScratchSpaceMallocMessageBuilder<'a> {
+    fn mut_arena(&mut self) -> &mut BuilderArena {
+        self.builder.mut_arena()
+    }
+    fn arena(&self) -> &BuilderArena {
+        self.builder.arena()
+    }
+}
+
+/// A message builder whose root has type `T`, where `T` is the `Owned` marker of a pointer type,
+/// for example `text::Owned` or a generated struct's `Owned`.
+pub struct TypedBuilder<T, A : Allocator = HeapAllocator> {
+    message : MallocMessageBuilder<A>,
+}
+
+impl <T> TypedBuilder<T, HeapAllocator> {
+    pub fn new_default() -> TypedBuilder<T, HeapAllocator> {
+        TypedBuilder::new(MallocMessageBuilder::new_default())
+    }
+}
+
+impl <T, A : Allocator> TypedBuilder<T, A> {
+    pub fn new(message : MallocMessageBuilder<A>) -> TypedBuilder<T, A> {
+        TypedBuilder { message : message }
+    }
+
+    pub fn init_root<'a>(&'a mut self) -> Result<<T as Owned<'a>>::Builder> where T : Owned<'a> {
+        self.message.init_root()
+    }
+
+    /// Like `init_root()`, for lists and blobs, which need to know their size up front.
+    pub fn init_root_sized<'a>(&'a mut self, size : u32) -> Result<<T as Owned<'a>>::Builder>
+        where T : Owned<'a> {
+        try!(self.message.get_root_internal()).init_as_sized(size)
+    }
+
+    pub fn get_root<'a>(&'a mut self) -> Result<<T as Owned<'a>>::Builder> where T : Owned<'a> {
+        self.message.get_root()
+    }
+
+    pub fn borrow_inner<'a>(&'a mut self) -> &'a mut MallocMessageBuilder<A> {
+        &mut self.message
+    }
+
+    pub fn into_inner(self) -> MallocMessageBuilder<A> {
+        self.message
+    }
+
+    /// Serializes the message into a single buffer and returns a reader over that buffer. The
+    /// message's capabilities are moved into the reader's cap table.
+    pub fn into_reader(mut self, options : ReaderOptions) -> Result<TypedReader<T>> {
+        let words = serialize::write_message_to_words(&self.message);
+        let mut reader = try!(serialize::read_message_from_owned_words(words, options));
+        reader.init_cap_table(::std::mem::replace(&mut self.message.mut_arena().cap_table, Vec::new()));
+        Ok(TypedReader::new(reader))
+    }
+}
+
+/// Copies `root` and everything it points at into a new single-segment message in canonical
+/// form, and returns the words of that message. Two structs with the same value always
+/// canonicalize to the same words.
+pub fn canonicalize(root : &layout::StructReader) -> Result<Vec<Word>> {
+    //# Canonicalization can only shrink the object, so the total size plus the root pointer
+    //# always fits in one segment.
+    let size = try!(root.total_size()).word_count + WORDS_PER_POINTER as u64;
+    if size >= (1 << 29) {
+        return Err(Error::new_decode_error("Message is too large to canonicalize.", None));
+    }
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(size as u32).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut message = try!(MallocMessageBuilder::new(options));
+    try!(message.get_root_internal());
+    unsafe {
+        let segment = &mut message.mut_arena().segment0 as *mut SegmentBuilder;
+        try!(layout::PointerBuilder::get_root(segment, (*segment).get_ptr_unchecked(0))
+             .set_struct_canonical(root));
+    }
+    if message.arena().more_segments.len() > 0 {
+        return Err(Error::new_decode_error("Canonicalized message did not fit in one segment.", None));
+    }
+
+    Ok(message.get_segments_for_output(|segments| {
+        let mut result = Word::allocate_zeroed_vec(segments[0].len());
+        unsafe {
+            ::std::ptr::copy_nonoverlapping_memory(result.as_mut_slice().as_mut_ptr(),
+                                                   segments[0].as_ptr(), segments[0].len());
+        }
+        result
+    }))
+}
+
+/// A message reader whose root has type `T`. See `TypedBuilder`.
+pub struct TypedReader<T, M : MessageReader = OwnedSpaceMessageReader> {
+    message : M,
+}
+
+impl <T, M : MessageReader> TypedReader<T, M> {
+    pub fn new(message : M) -> TypedReader<T, M> {
+        TypedReader { message : message }
+    }
+
+    pub fn get_root<'a>(&'a self) -> Result<<T as Owned<'a>>::Reader> where T : Owned<'a> {
+        self.message.get_root()
+    }
+
+    pub fn borrow_inner<'a>(&'a self) -> &'a M {
+        &self.message
+    }
+
+    pub fn into_inner(self) -> M {
+        self.message
+    }
+
+    /// Copies the message into a new builder whose first segment is big enough to hold it.
+    pub fn into_builder(self) -> Result<TypedBuilder<T, HeapAllocator>> {
+        let root = try!(self.message.get_root_internal());
+        let mut message = MallocMessageBuilder::with_size_hint(try!(root.total_size()));
+        try!(try!(message.get_root_internal()).set_as_copy_of(root));
+        Ok(TypedBuilder::new(message))
+    }
+}
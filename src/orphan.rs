@@ -0,0 +1,90 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Objects that live in a message but are not reachable from its root.
+//!
+//! An orphan can be built before it is known where it will go, and then adopted into any
+//! pointer in the same message without copying. Conversely, an existing field can be disowned
+//! and adopted somewhere else. An orphan that is dropped without being adopted is zeroed.
+
+use private::arena::BuilderArena;
+use private::layout::OrphanBuilder;
+use traits::{FromPointerBuilder, FromPointerReader, SetPointerBuilder};
+use Result;
+
+pub struct Orphanage<'a> {
+    marker : ::std::marker::ContravariantLifetime<'a>,
+    arena : *mut BuilderArena,
+}
+
+impl <'a> Orphanage<'a> {
+    /// The caller must ensure that `arena` outlives `'b`. Use `MessageBuilder::get_orphanage()`
+    /// or `any_pointer::Builder::get_orphanage()` instead.
+    pub unsafe fn new<'b>(arena : *mut BuilderArena) -> Orphanage<'b> {
+        Orphanage { marker : ::std::marker::ContravariantLifetime::<'b>, arena : arena }
+    }
+
+    pub fn new_orphan<T : FromPointerBuilder<'a>>(&self) -> Result<Orphan<'a, T>> {
+        self.new_orphan_sized(0)
+    }
+
+    pub fn new_orphan_sized<T : FromPointerBuilder<'a>>(&self, size : u32) -> Result<Orphan<'a, T>> {
+        let mut builder = unsafe { OrphanBuilder::new(self.arena) };
+        let _ : T = try!(FromPointerBuilder::init_pointer(builder.as_pointer_builder(), size));
+        Ok(Orphan::new(builder))
+    }
+
+    pub fn new_orphan_copy<To, From : SetPointerBuilder<To>>(&self, value : From) -> Result<Orphan<'a, To>> {
+        let mut builder = unsafe { OrphanBuilder::new(self.arena) };
+        try!(SetPointerBuilder::<To>::set_pointer_builder(builder.as_pointer_builder(), value));
+        Ok(Orphan::new(builder))
+    }
+}
+
+pub struct Orphan<'a, T> {
+    builder : OrphanBuilder<'a>,
+}
+
+impl <'a, T> Orphan<'a, T> {
+    pub fn new<'b>(builder : OrphanBuilder<'b>) -> Orphan<'b, T> {
+        Orphan { builder : builder }
+    }
+
+    pub fn into_internal(self) -> OrphanBuilder<'a> {
+        self.builder
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.builder.is_null()
+    }
+}
+
+impl <'a, T : FromPointerBuilder<'a>> Orphan<'a, T> {
+    pub fn get(&mut self) -> Result<T> {
+        FromPointerBuilder::get_from_pointer(self.builder.as_pointer_builder())
+    }
+}
+
+impl <'a, T> Orphan<'a, T> {
+    pub fn get_reader<U : FromPointerReader<'a>>(&self) -> Result<U> {
+        FromPointerReader::get_from_pointer(&self.builder.as_reader())
+    }
+}
@@ -22,8 +22,18 @@
 //! List of primitives.
 
 use traits::{FromPointerReader, FromPointerBuilder};
+use traits;
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder,
                       PrimitiveElement, element_size_for_type};
+use Result;
+
+#[derive(Copy)]
+pub struct Owned<T>;
+
+impl <'a, T : PrimitiveElement> traits::Owned<'a> for Owned<T> {
+    type Reader = Reader<'a, T>;
+    type Builder = Builder<'a, T>;
+}
 
 #[derive(Copy)]
 pub struct Reader<'a, T> {
@@ -39,8 +49,8 @@ impl <'a, T : PrimitiveElement> Reader<'a, T> {
 }
 
 impl <'a, T : PrimitiveElement> FromPointerReader<'a> for Reader<'a, T> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a, T> {
-        Reader { reader : reader.get_list(element_size_for_type::<T>(), ::std::ptr::null()) }
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
+        Ok(Reader { reader : try!(reader.get_list(element_size_for_type::<T>(), ::std::ptr::null())) })
     }
 }
 
@@ -68,11 +78,11 @@ impl <'a, T : PrimitiveElement> Builder<'a, T> {
 }
 
 impl <'a, T : PrimitiveElement> FromPointerBuilder<'a> for Builder<'a, T> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a, T> {
-        Builder { builder : builder.init_list(element_size_for_type::<T>(), size) }
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a, T>> {
+        Ok(Builder { builder : try!(builder.init_list(element_size_for_type::<T>(), size)) })
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a, T> {
-        Builder { builder : builder.get_list(element_size_for_type::<T>(), ::std::ptr::null())}
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a, T>> {
+        Ok(Builder { builder : try!(builder.get_list(element_size_for_type::<T>(), ::std::ptr::null()))})
     }
 }
 
@@ -84,8 +94,9 @@ impl <'a, T : PrimitiveElement> Builder<'a, T> {
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a, T>) {
-        pointer.set_list(&value.reader);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a, T>) -> Result<()> {
+        pointer.set_list(&value.reader)
     }
 }
 
@@ -19,12 +19,11 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::cell::Cell;
 use private::capability::ClientHook;
 use private::units::*;
 use message;
-use Word;
-
-pub use self::FirstSegment::{NumWords, ZeroedWords};
+use {Error, Result, Word};
 
 pub type SegmentId = u32;
 
@@ -49,7 +48,6 @@ impl SegmentReader {
         let this_begin : usize = self.ptr as usize;
         let this_end : usize = unsafe { self.ptr.offset(self.size as isize) as usize };
         return from as usize >= this_begin && to as usize <= this_end && from as usize <= to as usize;
-        // TODO readLimiter
     }
 }
 
@@ -103,6 +101,12 @@ impl SegmentBuilder {
         }
     }
 
+    /// Zeroes the words that have been allocated so far and makes them available again.
+    pub fn reset(&mut self) {
+        unsafe { ::std::ptr::zero_memory(self.reader.ptr as *mut Word, self.current_size() as usize); }
+        self.pos = self.reader.ptr as *mut Word;
+    }
+
     #[inline]
     pub fn get_ptr_unchecked(&self, offset : WordCount32) -> *mut Word {
         unsafe {
@@ -122,6 +126,41 @@ impl SegmentBuilder {
     }
 }
 
+/// Tracks how many words may still be read from a message, so that a message with many pointers
+/// aliasing the same data cannot amplify reads without bound.
+pub struct ReadLimiter {
+    pub limit : Cell<u64>,
+}
+
+impl ReadLimiter {
+    pub fn new(limit : u64) -> ReadLimiter {
+        ReadLimiter { limit : Cell::new(limit) }
+    }
+
+    #[inline]
+    pub fn can_read(&self, amount : WordCount64) -> bool {
+        let current = self.limit.get();
+        if amount > current {
+            false
+        } else {
+            self.limit.set(current - amount);
+            true
+        }
+    }
+
+    #[inline]
+    pub fn unread(&self, amount : WordCount64) {
+        //# Be careful not to overflow here. Since ReaderArena has no limit on the number of
+        //# segments, in theory the total size could exceed 2^64.
+        let current = self.limit.get();
+        if amount > ::std::u64::MAX - current {
+            self.limit.set(::std::u64::MAX);
+        } else {
+            self.limit.set(current + amount);
+        }
+    }
+}
+
 pub struct ReaderArena {
     //    message : *message::MessageReader<'a>,
     pub segment0 : SegmentReader,
@@ -131,7 +170,7 @@ pub struct ReaderArena {
 
     pub cap_table : Vec<Option<Box<ClientHook+Send>>>,
 
-    pub fail_fast : bool,
+    pub read_limiter : ReadLimiter,
 }
 
 impl ReaderArena {
@@ -145,7 +184,7 @@ impl ReaderArena {
             },
             more_segments : Vec::new(),
             cap_table : Vec::new(),
-            fail_fast : options.fail_fast,
+            read_limiter : ReadLimiter::new(options.traversal_limit_in_words),
         });
 
 
@@ -169,11 +208,14 @@ impl ReaderArena {
         arena
     }
 
-    pub fn try_get_segment(&self, id : SegmentId) -> *const SegmentReader {
+    pub fn try_get_segment(&self, id : SegmentId) -> Result<*const SegmentReader> {
         if id == 0 {
-            return &self.segment0 as *const SegmentReader;
+            Ok(&self.segment0 as *const SegmentReader)
+        } else if (id as usize) <= self.more_segments.len() {
+            Ok(&self.more_segments.as_slice()[id as usize - 1] as *const SegmentReader)
         } else {
-            unsafe { self.more_segments.get_unchecked(id as usize - 1) as *const SegmentReader }
+            Err(Error::new_decode_error("Message contains far pointer to unknown segment.",
+                                        Some(format!("{}", id))))
         }
     }
 
@@ -187,52 +229,23 @@ impl ReaderArena {
 pub struct BuilderArena {
     pub segment0 : SegmentBuilder,
     pub more_segments : Vec<Box<SegmentBuilder>>,
-    pub allocation_strategy : message::AllocationStrategy,
-    pub owned_memory : Vec<*mut Word>,
-    pub next_size : u32,
-    pub cap_table : Vec<Option<Box<ClientHook+Send>>>,
-    pub fail_fast : bool,
-}
-
-impl Drop for BuilderArena {
-    fn drop(&mut self) {
-        unsafe {
-            ::std::rt::heap::deallocate(::std::mem::transmute(self.segment0.reader.ptr),
-                                        BYTES_PER_WORD * self.segment0.reader.size as usize,
-                                        BYTES_PER_WORD);
-        }
-        for ref segment_builder in self.more_segments.iter() {
-            unsafe {
-                ::std::rt::heap::deallocate(::std::mem::transmute(segment_builder.reader.ptr),
-                                            BYTES_PER_WORD * segment_builder.reader.size as usize,
-                                            BYTES_PER_WORD);
-            }
-        }
-    }
-}
+    allocator : *mut message::Allocator,
 
-pub enum FirstSegment<'a> {
-    NumWords(u32),
-    ZeroedWords(&'a mut [Word])
+    // The id of the first segment that allocations are tried in. Segments before it are treated
+    // as full.
+    segment_with_space : SegmentId,
+    pub cap_table : Vec<Option<Box<ClientHook+Send>>>,
 }
 
 impl BuilderArena {
 
-    pub fn new(allocation_strategy : message::AllocationStrategy,
-               first_segment : FirstSegment,
-               fail_fast : bool) -> Box<BuilderArena> {
-        let (first_segment, num_words, owned_memory) : (*mut Word, u32, Vec<*mut Word>) = unsafe {
-            match first_segment {
-                NumWords(n) => {
-                    let ptr : *mut Word = ::std::mem::transmute(
-                        ::std::rt::heap::allocate(BYTES_PER_WORD * n as usize,
-                                                  ::std::mem::min_align_of::<Word>()));
-                    if ptr.is_null() {panic!("could not allocate segment")}
-                    ::std::ptr::zero_memory(ptr, n as usize);
-                    (ptr, n, vec!(ptr))
-                }
-                ZeroedWords(w) => (w.as_mut_ptr(), w.len() as u32, Vec::new())
-            }};
+    /// Creates an arena whose segments come from `allocator`, with a first segment of at least
+    /// `first_segment_minimum` words. The caller must keep `allocator` alive until after it has
+    /// called `deallocate_segments()`.
+    pub fn new(allocator : *mut message::Allocator,
+               first_segment_minimum : WordCount32) -> Result<Box<BuilderArena>> {
+        let (first_segment, num_words) =
+            try!(unsafe { (*allocator).allocate_segment(first_segment_minimum) });
 
         let mut result = Box::new(BuilderArena {
             segment0 : SegmentBuilder {
@@ -244,65 +257,78 @@ impl BuilderArena {
                 pos : first_segment,
             },
             more_segments : Vec::new(),
-            allocation_strategy : allocation_strategy,
-            owned_memory : owned_memory,
-            next_size : num_words,
+            allocator : allocator,
+            segment_with_space : 0,
             cap_table : Vec::new(),
-            fail_fast : fail_fast,
         });
 
         let arena_ptr = { let ref mut ptr = *result; ptr as *mut BuilderArena};
         result.segment0.reader.arena = ArenaPtr::Builder(arena_ptr);
 
-        result
+        Ok(result)
     }
 
-    pub fn allocate_owned_memory(&mut self, minimum_size : WordCount32) -> (*mut Word, WordCount32) {
-        let size = ::std::cmp::max(minimum_size, self.next_size);
-        let new_words : *mut Word = unsafe {
-            ::std::mem::transmute(::std::rt::heap::allocate(BYTES_PER_WORD * size as usize,
-                                                            ::std::mem::min_align_of::<Word>())) };
-        if new_words.is_null() { panic!("could not allocate a new segment.") }
-        unsafe { ::std::ptr::zero_memory(new_words, size as usize) };
-
-        self.owned_memory.push(new_words);
+    /// Returns the number of words allocated to objects so far, across all segments.
+    pub fn words_used(&self) -> u64 {
+        let mut result = self.segment0.current_size() as u64;
+        for segment_builder in self.more_segments.iter() {
+            result += segment_builder.current_size() as u64;
+        }
+        result
+    }
 
-        match self.allocation_strategy {
-            message::AllocationStrategy::GrowHeuristically => { self.next_size += size; }
-            _ => { }
+    /// Empties the arena while keeping all of its segments, so that it can be used to build
+    /// another message. Only the words that were actually used get zeroed.
+    pub fn reset(&mut self) {
+        self.segment0.reset();
+        for segment_builder in self.more_segments.iter_mut() {
+            segment_builder.reset();
         }
-        (new_words, size)
+        self.segment_with_space = 0;
+        self.cap_table = Vec::new();
     }
 
+    /// Hands every segment back to the allocator. Must be called exactly once, after which the
+    /// arena must not be used.
+    pub fn deallocate_segments(&mut self) {
+        unsafe {
+            (*self.allocator).deallocate_segment(self.segment0.reader.ptr as *mut Word,
+                                                 self.segment0.reader.size,
+                                                 self.segment0.current_size());
+            for segment_builder in self.more_segments.iter() {
+                (*self.allocator).deallocate_segment(segment_builder.reader.ptr as *mut Word,
+                                                     segment_builder.reader.size,
+                                                     segment_builder.current_size());
+            }
+        }
+    }
 
     #[inline]
-    pub fn allocate(&mut self, amount : WordCount32) -> (*mut SegmentBuilder, *mut Word) {
+    pub fn allocate(&mut self, amount : WordCount32) -> Result<(*mut SegmentBuilder, *mut Word)> {
         unsafe {
-            match self.segment0.allocate(amount) {
-                Some(result) => { return ((&mut self.segment0) as *mut SegmentBuilder, result) }
-                None => {}
+            // Like the C++ implementation, once an allocation doesn't fit in a segment we stop
+            // trying that segment, so that allocating never has to scan every segment. After a
+            // `reset()`, this walks forward through the segments that were kept.
+            let num_segments = self.more_segments.len() as SegmentId + 1;
+            while self.segment_with_space < num_segments {
+                let segment = self.get_segment(self.segment_with_space);
+                match (*segment).allocate(amount) {
+                    Some(result) => { return Ok((segment, result)) }
+                    None => {}
+                }
+                self.segment_with_space += 1;
             }
 
-            //# Need to fall back to additional segments.
-
-            let id = {
-                let len = self.more_segments.len();
-                if len == 0 { 1 }
-                else {
-                    let result_ptr = &mut *self.more_segments.as_mut_slice()[len-1] as *mut SegmentBuilder;
-                    match self.more_segments.as_mut_slice()[len - 1].allocate(amount) {
-                        Some(result) => { return (result_ptr, result) }
-                        None => { len + 1 }
-                    }
-                }};
+            //# Need to allocate a new segment.
+            let id = self.more_segments.len() + 1;
 
-            let (words, size) = self.allocate_owned_memory(amount);
+            let (words, size) = try!((*self.allocator).allocate_segment(amount));
             let mut new_builder = Box::new(SegmentBuilder::new(self, id as u32, words, size));
             let builder_ptr = &mut *new_builder as *mut SegmentBuilder;
 
             self.more_segments.push(new_builder);
 
-            (builder_ptr, (*builder_ptr).allocate(amount).unwrap() )
+            Ok((builder_ptr, (*builder_ptr).allocate(amount).unwrap()))
         }
     }
 
@@ -314,9 +340,16 @@ impl BuilderArena {
         }
     }
 
+    /// Calls `cont` with the used part of each segment. Segments left empty at the end, e.g. ones
+    /// kept across a `reset()` that the new message didn't need, are not included.
     pub fn get_segments_for_output<T, U : FnMut(&[&[Word]]) -> T>(&self, mut cont : U) -> T {
         unsafe {
-            if self.more_segments.len() == 0 {
+            let mut num_more_segments = self.more_segments.len();
+            while num_more_segments > 0 && self.more_segments[num_more_segments - 1].current_size() == 0 {
+                num_more_segments -= 1;
+            }
+
+            if num_more_segments == 0 {
                 let v = ::std::slice::from_raw_parts::<Word>(
                     self.segment0.reader.ptr,
                     self.segment0.current_size() as usize);
@@ -325,7 +358,7 @@ impl BuilderArena {
                 let mut result = Vec::new();
                 result.push(::std::slice::from_raw_parts(self.segment0.reader.ptr,
                                                          self.segment0.current_size() as usize));
-                for seg in self.more_segments.iter() {
+                for seg in self.more_segments[..num_more_segments].iter() {
                     result.push(::std::slice::from_raw_parts(seg.reader.ptr,
                                                              seg.current_size() as usize))
                 }
@@ -342,6 +375,12 @@ impl BuilderArena {
         self.cap_table.push(Some(cap));
         self.cap_table.len() as u32 - 1
     }
+
+    pub fn drop_cap(&mut self, index : u32) {
+        if (index as usize) < self.cap_table.len() {
+            self.cap_table.as_mut_slice()[index as usize] = None;
+        }
+    }
 }
 
 #[derive(Copy)]
@@ -352,7 +391,7 @@ pub enum ArenaPtr {
 }
 
 impl ArenaPtr {
-    pub fn try_get_segment(&self, id : SegmentId) -> *const SegmentReader {
+    pub fn try_get_segment(&self, id : SegmentId) -> Result<*const SegmentReader> {
         unsafe {
             match self {
                 &ArenaPtr::Reader(reader) => {
@@ -360,9 +399,12 @@ impl ArenaPtr {
                 }
                 &ArenaPtr::Builder(builder) => {
                     if id == 0 {
-                        &(*builder).segment0.reader as *const SegmentReader
+                        Ok(&(*builder).segment0.reader as *const SegmentReader)
+                    } else if (id as usize) <= (*builder).more_segments.len() {
+                        Ok(&(*builder).more_segments.as_slice()[id as usize - 1].reader as *const SegmentReader)
                     } else {
-                        &(*builder).more_segments.as_slice()[id as usize - 1].reader as *const SegmentReader
+                        Err(Error::new_decode_error("Message contains far pointer to unknown segment.",
+                                                    Some(format!("{}", id))))
                     }
                 }
                 &ArenaPtr::Null => {
@@ -372,6 +414,17 @@ impl ArenaPtr {
         }
     }
 
+    pub fn has_cap(&self, index : usize) -> bool {
+        unsafe {
+            let cap_table = match self {
+                &ArenaPtr::Reader(reader) => { &(*reader).cap_table }
+                &ArenaPtr::Builder(builder) => { &(*builder).cap_table }
+                &ArenaPtr::Null => { return false; }
+            };
+            index < cap_table.len() && cap_table.as_slice()[index].is_some()
+        }
+    }
+
     pub fn extract_cap(&self, index : usize) -> Option<Box<ClientHook+Send>> {
         unsafe {
             match self {
@@ -400,20 +453,23 @@ impl ArenaPtr {
                     }
                 }
                 &ArenaPtr::Null => {
-                    panic!();
+                    // Values that don't belong to any message, such as default values, have no
+                    // cap table.
+                    None
                 }
             }
         }
     }
 
-    pub fn fail_fast(&self) -> bool {
+    #[inline]
+    pub fn can_read(&self, amount : WordCount64) -> bool {
         unsafe {
             match self {
                 &ArenaPtr::Reader(reader) => {
-                    (*reader).fail_fast
+                    (*reader).read_limiter.can_read(amount)
                 }
-                &ArenaPtr::Builder(builder) => {
-                    (*builder).fail_fast
+                &ArenaPtr::Builder(_) => {
+                    true
                 }
                 &ArenaPtr::Null => {
                     panic!()
@@ -422,4 +478,18 @@ impl ArenaPtr {
         }
     }
 
+    #[inline]
+    pub fn unread(&self, amount : WordCount64) {
+        unsafe {
+            match self {
+                &ArenaPtr::Reader(reader) => {
+                    (*reader).read_limiter.unread(amount)
+                }
+                &ArenaPtr::Builder(_) => { }
+                &ArenaPtr::Null => {
+                    panic!()
+                }
+            }
+        }
+    }
 }
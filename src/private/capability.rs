@@ -95,3 +95,85 @@ pub enum PipelineOp {
     GetPointerField(u16),
 }
 
+
+/// Creates the message for a new request. Implementations of `ClientHook::new_call()` should use
+/// this so that a request whose parameters have a known size fits in a single segment.
+pub fn new_request_message(size_hint : Option<MessageSize>) -> MallocMessageBuilder {
+    match size_hint {
+        Some(size) => MallocMessageBuilder::with_size_hint(size),
+        None => MallocMessageBuilder::new_default(),
+    }
+}
+
+/// A client whose every call fails. Used in place of a capability that could not be
+/// read from a message, so that only calls made on that capability are affected.
+pub struct BrokenClient {
+    description : String,
+}
+
+impl BrokenClient {
+    pub fn new(description : String) -> BrokenClient {
+        BrokenClient { description : description }
+    }
+}
+
+pub fn new_broken_cap(description : &str) -> Box<ClientHook+Send> {
+    Box::new(BrokenClient::new(description.to_string()))
+}
+
+impl ClientHook for BrokenClient {
+    fn copy(&self) -> Box<ClientHook+Send> {
+        Box::new(BrokenClient::new(self.description.clone()))
+    }
+
+    fn new_call(&self,
+                _interface_id : u64,
+                _method_id : u16,
+                size_hint : Option<MessageSize>)
+                -> Request<any_pointer::Builder, any_pointer::Reader, any_pointer::Pipeline> {
+        Request::new(Box::new(BrokenRequest { message : new_request_message(size_hint),
+                                              description : self.description.clone() }))
+    }
+
+    fn call(&self, _interface_id : u64, _method_id : u16, context : Box<CallContextHook+Send>) {
+        context.fail(self.description.clone());
+    }
+
+    fn get_descriptor(&self) -> Box<::std::any::Any + 'static> {
+        Box::new(())
+    }
+}
+
+struct BrokenRequest {
+    message : MallocMessageBuilder,
+    description : String,
+}
+
+impl RequestHook for BrokenRequest {
+    fn message<'a>(&'a mut self) -> &'a mut MallocMessageBuilder {
+        &mut self.message
+    }
+
+    fn send<'a>(self : Box<Self>) -> ResultFuture<any_pointer::Reader<'a>, any_pointer::Pipeline> {
+        // Nobody will ever answer, so drop the sending half right away.
+        let (_, answer_port) = ::std::sync::mpsc::channel();
+        let pipeline = BrokenPipeline { description : self.description.clone() };
+        ResultFuture { answer_port : answer_port,
+                       answer_result : Err(()),
+                       pipeline : any_pointer::Pipeline::new(Box::new(pipeline)) }
+    }
+}
+
+struct BrokenPipeline {
+    description : String,
+}
+
+impl PipelineHook for BrokenPipeline {
+    fn copy(&self) -> Box<PipelineHook+Send> {
+        Box::new(BrokenPipeline { description : self.description.clone() })
+    }
+
+    fn get_pipelined_cap(&self, _ops : Vec<PipelineOp>) -> Box<ClientHook+Send> {
+        new_broken_cap(self.description.as_slice())
+    }
+}
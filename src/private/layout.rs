@@ -19,6 +19,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::hash::{Hasher, SipHasher};
 use data;
 use text;
 use private::capability::{ClientHook};
@@ -26,7 +27,8 @@ use private::arena::*;
 use private::endian::{WireValue, Endian};
 use private::mask::*;
 use private::units::*;
-use {MessageSize, Word};
+use validate::{PathPattern, Problem};
+use {MessageSize, Result, Word};
 
 pub use self::ElementSize::{Void, Bit, Byte, TwoBytes, FourBytes, EightBytes, Pointer, InlineComposite};
 
@@ -242,6 +244,21 @@ impl WirePointer {
         self.offset_and_kind.set(0xfffffffc);
     }
 
+    #[inline]
+    pub fn set_kind_for_orphan(&mut self, kind : WirePointerKind) {
+        //# OrphanBuilder contains a WirePointer, but since it isn't located
+        //# in a segment, it should not have a valid offset (unless it is a
+        //# FAR or OTHER pointer). We set its offset to -1 because setting it
+        //# to zero would mean a pointer to an empty struct would appear to be
+        //# a null pointer.
+        self.offset_and_kind.set(kind as u32 | 0xfffffffc);
+    }
+
+    #[inline]
+    pub fn is_positional(&self) -> bool {
+        (self.offset_and_kind.get() & 2) == 0 //# STRUCT or LIST
+    }
+
     #[inline]
     pub fn inline_composite_list_element_count(&self) -> ElementCount32 {
         (self.offset_and_kind.get() >> 2)
@@ -329,38 +346,23 @@ struct SegmentAnd<T> {
 }
 
 macro_rules! require(
-    ($condition:expr, $segment:expr, $message:expr, $fail:stmt) => (
+    ($condition:expr, $message:expr) => (
         if !($condition) {
-            let _ = ::std::old_io::stdio::stderr().write_line($message);
-            if ($segment).arena.fail_fast() {
-                panic!();
-            }
-            $fail
-        }
-        );
-    );
-
-macro_rules! require_panic(
-    ($segment:expr, $message:expr, $fail:stmt) => (
-        {
-            let _ = ::std::old_io::stdio::stderr().write_line($message);
-            if ($segment).arena.fail_fast() {
-                panic!();
-            }
-            $fail;
+            return Err(::Error::new_decode_error($message, None));
         }
         );
     );
 
 
 mod wire_helpers {
-    use private::capability::ClientHook;
+    use private::capability::{ClientHook, new_broken_cap};
     use private::arena::*;
     use private::layout::*;
     use private::units::*;
     use data;
     use text;
-    use {MessageSize, Word};
+    use validate::{self, PathElement, PathPattern, Problem};
+    use {Error, MessageSize, Result, Word};
 
     #[inline]
     pub fn round_bytes_up_to_words(bytes : ByteCount32) -> WordCount32 {
@@ -391,10 +393,31 @@ mod wire_helpers {
         return segment.is_null() || (*segment).contains_interval(start, end);
     }
 
+    #[inline]
+    pub unsafe fn amplified_read(segment : *const SegmentReader,
+                                 virtual_amount : WordCount64) -> Result<()> {
+        //# If segment is null, this is an unchecked message, so there is no read limit.
+        require!(segment.is_null() || (*segment).arena.can_read(virtual_amount),
+                 "Exceeded message traversal limit. See capnp::ReaderOptions.");
+        Ok(())
+    }
+
     #[inline]
     pub unsafe fn allocate(reff : &mut *mut WirePointer,
                            segment : &mut *mut SegmentBuilder,
-                           amount : WordCount32, kind : WirePointerKind) -> *mut Word {
+                           amount : WordCount32, kind : WirePointerKind,
+                           orphan : *mut OrphanBuilder) -> Result<*mut Word> {
+        if !orphan.is_null() {
+            //# orphan is non-null. Allocate an orphan.
+            zero_orphan(orphan);
+            let (orphan_segment, ptr) = try!((*(**segment).get_arena()).allocate(amount));
+            *segment = orphan_segment;
+            (**reff).set_kind_for_orphan(kind);
+            (*orphan).segment = orphan_segment;
+            (*orphan).location = ptr;
+            return Ok(ptr);
+        }
+
         let is_null = (**reff).is_null();
         if !is_null {
             zero_object(*segment, *reff)
@@ -402,7 +425,7 @@ mod wire_helpers {
 
         if amount == 0 && kind == WirePointerKind::Struct {
             (**reff).set_kind_and_target_for_empty_struct();
-            return ::std::mem::transmute(reff);
+            return Ok(::std::mem::transmute(reff));
         }
 
         match (**segment).allocate(amount) {
@@ -413,7 +436,7 @@ mod wire_helpers {
                 //# the landing pad for a far pointer.
 
                 let amount_plus_ref = amount + POINTER_SIZE_IN_WORDS as u32;
-                let allocation = (*(**segment).get_arena()).allocate(amount_plus_ref);
+                let allocation = try!((*(**segment).get_arena()).allocate(amount_plus_ref));
                 *segment = allocation.0;
                 let ptr = allocation.1;
 
@@ -428,11 +451,11 @@ mod wire_helpers {
 
                 let ptr1 = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
                 (**reff).set_kind_and_target(kind, ptr1, *segment);
-                return ptr1;
+                return Ok(ptr1);
             }
             Some(ptr) => {
                 (**reff).set_kind_and_target(kind, ptr, *segment);
-                return ptr;
+                return Ok(ptr);
             }
         }
     }
@@ -473,31 +496,43 @@ mod wire_helpers {
         }
     }
 
+    #[inline]
+    pub unsafe fn builder_ref_target(reff : *mut WirePointer,
+                                     segment : &mut *mut SegmentBuilder,
+                                     orphan : *mut OrphanBuilder) -> *mut Word {
+        // An orphan's tag does not live in the message, so its offset means nothing. The
+        // orphan keeps track of where its object is, and in which segment.
+        if orphan.is_null() {
+            (*reff).mut_target()
+        } else {
+            *segment = (*orphan).segment;
+            (*orphan).location
+        }
+    }
+
     #[inline]
     pub unsafe fn follow_fars(reff: &mut *const WirePointer,
                               ref_target: *const Word,
-                              segment : &mut *const SegmentReader) -> *const Word {
+                              segment : &mut *const SegmentReader) -> Result<*const Word> {
 
         //# If the segment is null, this is an unchecked message,
         //# so there are no FAR pointers.
         if !(*segment).is_null() && (**reff).kind() == WirePointerKind::Far {
             *segment =
-                (**segment).arena.try_get_segment((**reff).far_ref().segment_id.get());
+                try!((**segment).arena.try_get_segment((**reff).far_ref().segment_id.get()));
 
             let ptr : *const Word = (**segment).get_start_ptr().offset(
                 (**reff).far_position_in_segment() as isize);
 
             let pad_words : isize = if (**reff).is_double_far() { 2 } else { 1 };
             require!(bounds_check(*segment, ptr, ptr.offset(pad_words)),
-                     **segment,
-                     "Message contains out-of-bounds far pointer.",
-                     return ::std::ptr::null());
+                     "Message contains out-of-bounds far pointer.");
 
             let pad : *const WirePointer = ::std::mem::transmute(ptr);
 
             if !(**reff).is_double_far() {
                 *reff = pad;
-                return (*pad).target();
+                return Ok((*pad).target());
             } else {
                 //# Landing pad is another far pointer. It is
                 //# followed by a tag describing the pointed-to
@@ -506,12 +541,12 @@ mod wire_helpers {
                 *reff = pad.offset(1);
 
                 *segment =
-                    (**segment).arena.try_get_segment((*pad).far_ref().segment_id.get());
+                    try!((**segment).arena.try_get_segment((*pad).far_ref().segment_id.get()));
 
-                return (**segment).get_start_ptr().offset((*pad).far_position_in_segment() as isize);
+                return Ok((**segment).get_start_ptr().offset((*pad).far_position_in_segment() as isize));
             }
         } else {
-            return ref_target;
+            return Ok(ref_target);
         }
     }
 
@@ -521,10 +556,17 @@ mod wire_helpers {
         //# reachable.
 
         match (*reff).kind() {
-            WirePointerKind::Struct | WirePointerKind::List | WirePointerKind::Other => {
+            WirePointerKind::Struct | WirePointerKind::List => {
                 zero_object_helper(segment,
                                  reff, (*reff).mut_target())
             }
+            WirePointerKind::Other => {
+                //# There is no object to zero. If this is a capability, release it from the
+                //# cap table. Otherwise we don't know what it is, so leave it alone.
+                if (*reff).is_capability() {
+                    (*(*segment).get_arena()).drop_cap((*reff).cap_ref().index.get());
+                }
+            }
             WirePointerKind::Far => {
                 segment = (*(*segment).get_arena()).get_segment((*reff).far_ref().segment_id.get());
                 let pad : *mut WirePointer =
@@ -551,7 +593,10 @@ mod wire_helpers {
                                      tag : *mut WirePointer,
                                      ptr: *mut Word) {
         match (*tag).kind() {
-            WirePointerKind::Other => { panic!("Don't know how to handle OTHER") }
+            WirePointerKind::Other => {
+                //# Unknown pointer type. We don't know the extent of the
+                //# object, so there is nothing we can safely zero.
+            }
             WirePointerKind::Struct => {
                 let pointer_section : *mut WirePointer =
                     ::std::mem::transmute(
@@ -569,10 +614,10 @@ mod wire_helpers {
                     Bit | Byte | TwoBytes | FourBytes | EightBytes => {
                         ::std::ptr::set_memory(
                             ptr, 0u8,
-                            round_bits_up_to_words((
-                                    (*tag).list_ref().element_count() *
-                                        data_bits_per_element(
-                                        (*tag).list_ref().element_size())) as u64) as usize)
+                            round_bits_up_to_words(
+                                (*tag).list_ref().element_count() as u64 *
+                                    data_bits_per_element((*tag).list_ref().element_size()) as u64)
+                                as usize)
                     }
                     Pointer => {
                         let count = (*tag).list_ref().element_count() as usize;
@@ -585,8 +630,11 @@ mod wire_helpers {
                     InlineComposite => {
                         let element_tag : *mut WirePointer = ::std::mem::transmute(ptr);
 
-                        assert!((*element_tag).kind() == WirePointerKind::Struct,
-                                "Don't know how to handle non-STRUCT inline composite");
+                        if (*element_tag).kind() != WirePointerKind::Struct {
+                            //# Don't know how to handle non-STRUCT inline composite,
+                            //# so leave it in place.
+                            return;
+                        }
 
                         let data_size = (*element_tag).struct_ref().data_size.get();
                         let pointer_count = (*element_tag).struct_ref().ptr_count.get();
@@ -602,7 +650,7 @@ mod wire_helpers {
                             }
                         }
                         ::std::ptr::set_memory(ptr, 0u8,
-                                               ((*element_tag).struct_ref().word_size() * count + 1) as usize);
+                                               (*element_tag).struct_ref().word_size() as usize * count as usize + 1);
                     }
                 }
             }
@@ -610,6 +658,21 @@ mod wire_helpers {
         }
     }
 
+    pub unsafe fn zero_orphan(orphan : *mut OrphanBuilder) {
+        // Zero out the object owned by `orphan`, and then its tag. Positional
+        // tags don't encode where the object is, so use the orphan's location.
+        let tag : *mut WirePointer = &mut (*orphan).tag;
+        if !(*tag).is_null() {
+            if (*tag).is_positional() {
+                zero_object_helper((*orphan).segment, tag, (*orphan).location);
+            } else {
+                zero_object((*orphan).segment, tag);
+            }
+            ::std::ptr::zero_memory(tag, 1);
+        }
+        (*orphan).location = ::std::ptr::null_mut();
+    }
+
     #[inline]
     pub unsafe fn zero_pointer_and_fars(segment : *mut SegmentBuilder, reff : *mut WirePointer) {
         //# Zero out the pointer itself and, if it is a far pointer,
@@ -625,70 +688,100 @@ mod wire_helpers {
         ::std::ptr::zero_memory(reff, 1);
     }
 
+    /// Allocates space for an upgraded copy of the object that `reff` points at, leaving the old
+    /// object in place for the caller to copy from. If the allocation fails, `reff` still points
+    /// at the old object.
+    pub unsafe fn allocate_for_upgrade(reff : &mut *mut WirePointer,
+                                       segment : &mut *mut SegmentBuilder,
+                                       amount : WordCount32, kind : WirePointerKind,
+                                       orphan : *mut OrphanBuilder) -> Result<*mut Word> {
+        let old_ref = *reff;
+        let old_segment = *segment;
+        let mut old_tag : WirePointer = ::std::ptr::read(old_ref);
+        let old_location = if orphan.is_null() { ::std::ptr::null_mut() } else { (*orphan).location };
+
+        //# Don't let allocate() zero out the object just yet.
+        ::std::ptr::zero_memory(old_ref, 1);
+
+        match allocate(reff, segment, amount, kind, orphan) {
+            Ok(ptr) => {
+                // Only now that the new space exists do we drop the old landing pads, if any.
+                zero_pointer_and_fars(old_segment, &mut old_tag);
+                Ok(ptr)
+            }
+            Err(e) => {
+                ::std::ptr::write(old_ref, old_tag);
+                if !orphan.is_null() {
+                    (*orphan).location = old_location;
+                }
+                Err(e)
+            }
+        }
+    }
+
     pub unsafe fn total_size(mut segment : *const SegmentReader,
                              mut reff : *const WirePointer,
-                             mut nesting_limit : i32) -> MessageSize {
+                             ref_target : *const Word,
+                             mut nesting_limit : i32) -> Result<MessageSize> {
         let mut result = MessageSize { word_count : 0, cap_count : 0};
 
-        if (*reff).is_null() { return result };
+        if (*reff).is_null() { return Ok(result) };
 
-        require!(nesting_limit > 0, *segment, "Message is too deeply nested.", return result);
+        require!(nesting_limit > 0, "Message is too deeply nested.");
 
         nesting_limit -= 1;
 
-        let ptr = follow_fars(&mut reff, (*reff).target(), &mut segment);
+        let ptr = try!(follow_fars(&mut reff, ref_target, &mut segment));
 
         match (*reff).kind() {
             WirePointerKind::Struct => {
                 require!(bounds_check(segment, ptr, ptr.offset((*reff).struct_ref().word_size() as isize)),
-                         *segment,
-                        "Message contains out-of-bounds struct pointer.",
-                         return result);
+                         "Message contains out-of-bounds struct pointer.");
+                try!(amplified_read(segment, (*reff).struct_ref().word_size() as u64));
                 result.word_count += (*reff).struct_ref().word_size() as u64;
 
                 let pointer_section : *const WirePointer =
                     ::std::mem::transmute(ptr.offset((*reff).struct_ref().data_size.get() as isize));
                 let count : isize = (*reff).struct_ref().ptr_count.get() as isize;
                 for i in 0..count {
-                    result.plus_eq(total_size(segment, pointer_section.offset(i), nesting_limit));
+                    let pointer = pointer_section.offset(i);
+                    result.plus_eq(try!(total_size(segment, pointer, (*pointer).target(), nesting_limit)));
                 }
             }
             WirePointerKind::List => {
                 match (*reff).list_ref().element_size() {
-                    Void => {}
+                    Void => {
+                        try!(amplified_read(segment, (*reff).list_ref().element_count() as u64));
+                    }
                     Bit | Byte | TwoBytes | FourBytes | EightBytes => {
                         let total_words = round_bits_up_to_words(
                             (*reff).list_ref().element_count() as u64 *
                                 data_bits_per_element((*reff).list_ref().element_size()) as u64);
                         require!(bounds_check(segment, ptr, ptr.offset(total_words as isize)),
-                                 *segment,
-                                 "Message contains out-of-bounds list pointer.",
-                                 return result);
+                                 "Message contains out-of-bounds list pointer.");
+                        try!(amplified_read(segment, total_words as u64));
                         result.word_count += total_words as u64;
                     }
                     Pointer => {
                         let count = (*reff).list_ref().element_count();
                         require!(bounds_check(segment, ptr, ptr.offset((count * WORDS_PER_POINTER as u32) as isize)),
-                                 *segment,
-                                 "Message contains out-of-bounds list pointer.",
-                                 return result);
+                                 "Message contains out-of-bounds list pointer.");
+                        try!(amplified_read(segment, count as u64 * WORDS_PER_POINTER as u64));
 
                         result.word_count += count as u64 * WORDS_PER_POINTER as u64;
 
                         for i in 0..count as isize {
+                            let pointer = ::std::mem::transmute::<*const Word,*const WirePointer>(ptr).offset(i);
                             result.plus_eq(
-                                total_size(segment,
-                                           ::std::mem::transmute::<*const Word,*const WirePointer>(ptr).offset(i),
-                                           nesting_limit));
+                                try!(total_size(segment, pointer, (*pointer).target(), nesting_limit)));
                         }
                     }
                     InlineComposite => {
                         let word_count = (*reff).list_ref().inline_composite_word_count();
                         require!(bounds_check(segment, ptr,
                                               ptr.offset(word_count as isize + POINTER_SIZE_IN_WORDS as isize)),
-                                 *segment,
-                                 "Message contains out-of-bounds list pointer.",
-                                 return result);
+                                 "Message contains out-of-bounds list pointer.");
+                        try!(amplified_read(segment, word_count as u64 + POINTER_SIZE_IN_WORDS as u64));
 
                         result.word_count += word_count as u64 + POINTER_SIZE_IN_WORDS as u64;
 
@@ -696,14 +789,16 @@ mod wire_helpers {
                         let count = (*element_tag).inline_composite_list_element_count();
 
                         require!((*element_tag).kind() == WirePointerKind::Struct,
-                                 *segment,
-                                 "Don't know how to handle non-STRUCT inline composite.",
-                                 return result);
+                                 "Don't know how to handle non-STRUCT inline composite.");
 
-                        require!((*element_tag).struct_ref().word_size() * count <= word_count,
-                                 *segment,
-                                 "InlineComposite list's elements overrun its word count",
-                                 return result);
+                        require!((*element_tag).struct_ref().word_size() as u64 * count as u64 <= word_count as u64,
+                                 "InlineComposite list's elements overrun its word count");
+
+                        if (*element_tag).struct_ref().word_size() == 0 {
+                            //# Watch out for lists of zero-sized structs, which can claim to be
+                            //# arbitrarily large without having sent actual data.
+                            try!(amplified_read(segment, count as u64));
+                        }
 
                         let data_size = (*element_tag).struct_ref().data_size.get();
                         let pointer_count = (*element_tag).struct_ref().ptr_count.get();
@@ -713,9 +808,9 @@ mod wire_helpers {
                             pos = pos.offset(data_size as isize);
 
                             for _ in 0..pointer_count {
+                                let pointer = ::std::mem::transmute::<*const Word,*const WirePointer>(pos);
                                 result.plus_eq(
-                                    total_size(segment, ::std::mem::transmute::<*const Word,*const WirePointer>(pos),
-                                               nesting_limit));
+                                    try!(total_size(segment, pointer, (*pointer).target(), nesting_limit)));
                                 pos = pos.offset(POINTER_SIZE_IN_WORDS as isize);
                             }
                         }
@@ -723,22 +818,22 @@ mod wire_helpers {
                 }
             }
             WirePointerKind::Far => {
-                panic!("Unexpected FAR pointer.");
+                return Err(Error::new_decode_error("Malformed double-far pointer.", None));
             }
             WirePointerKind::Other => {
                 if (*reff).is_capability() {
                     result.cap_count += 1;
                 } else {
-                    require_panic!(*segment, "Unknown pointer type.", return result);
+                    return Err(Error::new_decode_error("Unknown pointer type.", None));
                 }
             }
         }
 
-        result
+        Ok(result)
     }
 
     pub unsafe fn transfer_pointer(dst_segment : *mut SegmentBuilder, dst : *mut WirePointer,
-                                   src_segment : *mut SegmentBuilder, src : *mut WirePointer) {
+                                   src_segment : *mut SegmentBuilder, src : *mut WirePointer) -> Result<()> {
         //# Make *dst point to the same object as *src. Both must
         //# reside in the same message, but can be in different
         //# segments. Not always-inline because this is rarely used.
@@ -754,20 +849,28 @@ mod wire_helpers {
 
         if (*src).is_null() {
             ::std::ptr::zero_memory(dst, 1);
-        } else if (*src).kind() == WirePointerKind::Far {
+        } else if (*src).kind() == WirePointerKind::Far || (*src).kind() == WirePointerKind::Other {
+            //# Far and other pointers are position-independent, so we can just copy.
             ::std::ptr::copy_nonoverlapping_memory(dst, src as *const WirePointer, 1);
         } else {
-            transfer_pointer_split(dst_segment, dst, src_segment, src, (*src).mut_target());
+            try!(transfer_pointer_split(dst_segment, dst, src_segment, src, (*src).mut_target()));
         }
+        Ok(())
     }
 
     pub unsafe fn transfer_pointer_split(dst_segment : *mut SegmentBuilder, dst : *mut WirePointer,
                                          src_segment : *mut SegmentBuilder, src_tag : *mut WirePointer,
-                                         src_ptr : *mut Word) {
+                                         src_ptr : *mut Word) -> Result<()> {
         // Like the other transfer_pointer, but splits src into a tag and a
         // target. Particularly useful for OrphanBuilder.
 
-        if dst_segment == src_segment {
+        if (*src_tag).kind() == WirePointerKind::Struct && (*src_tag).struct_ref().word_size() == 0 {
+            // An empty struct has no content to point at, in any segment.
+            (*dst).set_kind_and_target_for_empty_struct();
+            ::std::ptr::copy_nonoverlapping_memory(&mut (*dst).upper32bits,
+                                                 &(*src_tag).upper32bits,
+                                                 1);
+        } else if dst_segment == src_segment {
             //# Same segment, so create a direct pointer.
             (*dst).set_kind_and_target((*src_tag).kind(), src_ptr, dst_segment);
 
@@ -784,7 +887,19 @@ mod wire_helpers {
             match (*src_segment).allocate(1) {
                 None => {
                     //# Darn, need a double-far.
-                    panic!("unimplemented");
+                    let (far_segment, landing_pad_word) = try!((*(*src_segment).get_arena()).allocate(2));
+                    let landing_pad : *mut WirePointer = ::std::mem::transmute(landing_pad_word);
+
+                    (*landing_pad).set_far(false, (*src_segment).get_word_offset_to(src_ptr));
+                    (*landing_pad).mut_far_ref().set((*src_segment).get_segment_id());
+
+                    let tag = landing_pad.offset(1);
+                    (*tag).set_kind_with_zero_offset((*src_tag).kind());
+                    ::std::ptr::copy_nonoverlapping_memory(&mut (*tag).upper32bits,
+                                                         &(*src_tag).upper32bits, 1);
+
+                    (*dst).set_far(true, (*far_segment).get_word_offset_to(landing_pad_word));
+                    (*dst).mut_far_ref().set((*far_segment).get_segment_id());
                 }
                 Some(landing_pad_word) => {
                     //# Simple landing pad is just a pointer.
@@ -799,16 +914,19 @@ mod wire_helpers {
                 }
             }
         }
+        Ok(())
     }
 
     #[inline]
     pub unsafe fn init_struct_pointer<'a>(mut reff : *mut WirePointer,
                                           mut segment_builder : *mut SegmentBuilder,
-                                          size : StructSize) -> StructBuilder<'a> {
-        let ptr : *mut Word = allocate(&mut reff, &mut segment_builder, size.total(), WirePointerKind::Struct);
+                                          size : StructSize,
+                                          orphan : *mut OrphanBuilder) -> Result<StructBuilder<'a>> {
+        let ptr : *mut Word = try!(allocate(&mut reff, &mut segment_builder, size.total(),
+                                            WirePointerKind::Struct, orphan));
         (*reff).mut_struct_ref().set_from_struct_size(size);
 
-        StructBuilder {
+        Ok(StructBuilder {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : segment_builder,
             data : ::std::mem::transmute(ptr),
@@ -816,34 +934,35 @@ mod wire_helpers {
                     ptr.offset((size.data as usize) as isize)),
             data_size : size.data as WordCount32 * (BITS_PER_WORD as BitCount32),
             pointer_count : size.pointers
-        }
+        })
     }
 
     #[inline]
     pub unsafe fn get_writable_struct_pointer<'a>(mut reff : *mut WirePointer,
                                                   mut segment : *mut SegmentBuilder,
                                                   size : StructSize,
-                                                  default_value : *const Word) -> StructBuilder<'a> {
-        let ref_target = (*reff).mut_target();
-        let mut first_time = true;
-
-        'use_default: loop {
-            if (*reff).is_null() | !first_time {
-                if default_value.is_null() ||
-                    (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
-                        return init_struct_pointer(reff, segment, size);
-                    }
-                unimplemented!()
-            }
-            first_time = false;
+                                                  default_value : *const Word,
+                                                  orphan : *mut OrphanBuilder) -> Result<StructBuilder<'a>> {
+        if (*reff).is_null() {
+            if default_value.is_null() ||
+                (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
+                    return init_struct_pointer(reff, segment, size, orphan);
+                }
+            //# Deep-copy the default value into the message. It is trusted, so it
+            //# is read as an unchecked segment.
+            let default_ref = ::std::mem::transmute::<*const Word,*const WirePointer>(default_value);
+            try!(copy_pointer(segment, reff, ::std::ptr::null(), default_ref, (*default_ref).target(),
+                              ::std::i32::MAX, false, orphan));
+        }
+
+        let ref_target = builder_ref_target(reff, &mut segment, orphan);
 
+        {
             let mut old_ref = reff;
             let mut old_segment = segment;
             let old_ptr = follow_builder_fars(&mut old_ref, ref_target, &mut old_segment);
             require!((*old_ref).kind() == WirePointerKind::Struct,
-                     (*segment).reader,
-                     "Message contains non-struct pointer where struct pointer was expected.",
-                     continue 'use_default);
+                     "Message contains non-struct pointer where struct pointer was expected.");
 
             let old_data_size = (*old_ref).struct_ref().data_size.get();
             let old_pointer_count = (*old_ref).struct_ref().ptr_count.get();
@@ -860,10 +979,8 @@ mod wire_helpers {
                 let new_pointer_count = ::std::cmp::max(old_pointer_count, size.pointers);
                 let total_size = new_data_size as u32 + new_pointer_count as u32 * WORDS_PER_POINTER as u32;
 
-                //# Don't let allocate() zero out the object just yet.
-                zero_pointer_and_fars(segment, reff);
-
-                let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::Struct);
+                let ptr = try!(allocate_for_upgrade(&mut reff, &mut segment, total_size,
+                                                    WirePointerKind::Struct, orphan));
                 (*reff).mut_struct_ref().set(new_data_size, new_pointer_count);
 
                 //# Copy data section.
@@ -876,29 +993,29 @@ mod wire_helpers {
                 let new_pointer_section : *mut WirePointer =
                     ::std::mem::transmute(ptr.offset(new_data_size as isize));
                 for i in 0..old_pointer_count as isize {
-                    transfer_pointer(segment, new_pointer_section.offset(i),
-                                     old_segment, old_pointer_section.offset(i));
+                    try!(transfer_pointer(segment, new_pointer_section.offset(i),
+                                     old_segment, old_pointer_section.offset(i)));
                 }
 
                 ::std::ptr::zero_memory(old_ptr, old_data_size as usize + old_pointer_count as usize);
 
-                return StructBuilder {
+                return Ok(StructBuilder {
                     marker : ::std::marker::ContravariantLifetime::<'a>,
                     segment : segment,
                     data : ::std::mem::transmute(ptr),
                     pointers : new_pointer_section,
                     data_size : new_data_size as u32 * BITS_PER_WORD as u32,
                     pointer_count : new_pointer_count
-                };
+                });
             } else {
-                return StructBuilder {
+                return Ok(StructBuilder {
                     marker : ::std::marker::ContravariantLifetime::<'a>,
                     segment : old_segment,
                     data : ::std::mem::transmute(old_ptr),
                     pointers : old_pointer_section,
                     data_size : old_data_size as u32 * BITS_PER_WORD as u32,
                     pointer_count : old_pointer_count
-                };
+                });
             }
         }
     }
@@ -907,19 +1024,22 @@ mod wire_helpers {
     pub unsafe fn init_list_pointer<'a>(mut reff : *mut WirePointer,
                                         mut segment_builder : *mut SegmentBuilder,
                                         element_count : ElementCount32,
-                                        element_size : ElementSize) -> ListBuilder<'a> {
+                                        element_size : ElementSize,
+                                        orphan : *mut OrphanBuilder) -> Result<ListBuilder<'a>> {
         assert!(element_size != InlineComposite,
                 "Should have called initStructListPointer() instead");
 
+        require!(element_count < (1 << 29), "Lists are limited to 2**29 elements.");
+
         let data_size = data_bits_per_element(element_size);
         let pointer_count = pointers_per_element(element_size);
         let step = data_size + pointer_count * BITS_PER_POINTER as u32;
         let word_count = round_bits_up_to_words(element_count as ElementCount64 * (step as u64));
-        let ptr = allocate(&mut reff, &mut segment_builder, word_count, WirePointerKind::List);
+        let ptr = try!(allocate(&mut reff, &mut segment_builder, word_count, WirePointerKind::List, orphan));
 
         (*reff).mut_list_ref().set(element_size, element_count);
 
-        ListBuilder {
+        Ok(ListBuilder {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : segment_builder,
             ptr : ::std::mem::transmute(ptr),
@@ -927,21 +1047,28 @@ mod wire_helpers {
             element_count : element_count,
             struct_data_size : data_size as u32,
             struct_pointer_count : pointer_count as u16
-        }
+        })
     }
 
     #[inline]
     pub unsafe fn init_struct_list_pointer<'a>(mut reff : *mut WirePointer,
                                                mut segment_builder : *mut SegmentBuilder,
                                                element_count : ElementCount32,
-                                               element_size : StructSize) -> ListBuilder<'a> {
+                                               element_size : StructSize,
+                                               orphan : *mut OrphanBuilder) -> Result<ListBuilder<'a>> {
+        require!(element_count < (1 << 29), "Lists are limited to 2**29 elements.");
+
         let words_per_element = element_size.total();
 
         //# Allocate the list, prefixed by a single WirePointer.
-        let word_count : WordCount32 = element_count * words_per_element;
+        let word_count64 = element_count as u64 * words_per_element as u64;
+        require!(word_count64 < (1 << 29),
+                 "Total size of struct list is larger than max segment size.");
+        let word_count = word_count64 as WordCount32;
         let ptr : *mut WirePointer =
-            ::std::mem::transmute(allocate(&mut reff, &mut segment_builder,
-                                          POINTER_SIZE_IN_WORDS as u32 + word_count, WirePointerKind::List));
+            ::std::mem::transmute(try!(allocate(&mut reff, &mut segment_builder,
+                                               POINTER_SIZE_IN_WORDS as u32 + word_count, WirePointerKind::List,
+                                               orphan)));
 
         //# Initialize the pointer.
         (*reff).mut_list_ref().set_inline_composite(word_count);
@@ -950,7 +1077,7 @@ mod wire_helpers {
 
         let ptr1 = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
 
-        ListBuilder {
+        Ok(ListBuilder {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : segment_builder,
             ptr : ::std::mem::transmute(ptr1),
@@ -958,31 +1085,33 @@ mod wire_helpers {
             element_count : element_count,
             struct_data_size : element_size.data as u32 * (BITS_PER_WORD as u32),
             struct_pointer_count : element_size.pointers
-        }
+        })
     }
 
     #[inline]
     pub unsafe fn get_writable_list_pointer<'a>(orig_ref : *mut WirePointer,
-                                                orig_segment : *mut SegmentBuilder,
+                                                mut orig_segment : *mut SegmentBuilder,
                                                 element_size : ElementSize,
-                                                default_value : *const Word) -> ListBuilder<'a> {
+                                                default_value : *const Word,
+                                                orphan : *mut OrphanBuilder) -> Result<ListBuilder<'a>> {
         assert!(element_size != InlineComposite,
                 "Use get_struct_list_{element,field}() for structs");
 
-        let orig_ref_target = (*orig_ref).mut_target();
-
-        let mut first_time = true;
+        if (*orig_ref).is_null() {
+            if default_value.is_null() ||
+                (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
+                    return Ok(ListBuilder::new_default());
+                }
+            //# Deep-copy the default value into the message. It is trusted, so it
+            //# is read as an unchecked segment.
+            let default_ref = ::std::mem::transmute::<*const Word,*const WirePointer>(default_value);
+            try!(copy_pointer(orig_segment, orig_ref, ::std::ptr::null(), default_ref, (*default_ref).target(),
+                              ::std::i32::MAX, false, orphan));
+        }
 
-        'use_default : loop {
+        let orig_ref_target = builder_ref_target(orig_ref, &mut orig_segment, orphan);
 
-            if (*orig_ref).is_null() || !first_time {
-                if default_value.is_null() ||
-                    (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
-                        return ListBuilder::new_default();
-                    }
-                unimplemented!()
-            }
-            first_time = false;
+        {
 
             //# We must verify that the pointer has the right size. Unlike
             //# in getWritableStructListReference(), we never need to
@@ -994,9 +1123,8 @@ mod wire_helpers {
             let mut segment = orig_segment;
             let mut ptr = follow_builder_fars(&mut reff, orig_ref_target, &mut segment);
 
-            require!((*reff).kind() == WirePointerKind::List, (*segment).reader,
-                     "Called get_list_{{field,element}}() but existing pointer is not a list",
-                     continue 'use_default);
+            require!((*reff).kind() == WirePointerKind::List,
+                     "Called get_list_{field,element}() but existing pointer is not a list");
 
             let old_size = (*reff).list_ref().element_size();
 
@@ -1013,10 +1141,8 @@ mod wire_helpers {
                 //# Read the tag to get the actual element count.
                 let tag : *const WirePointer = ::std::mem::transmute(ptr);
 
-                // capnproto-c++ doesn't go to useDefault here --- why not?
-                require!((*tag).kind() == WirePointerKind::Struct, (*segment).reader,
-                         "InlineComposite list with non-STRUCT elements not supported.",
-                         continue 'use_default);
+                require!((*tag).kind() == WirePointerKind::Struct,
+                         "InlineComposite list with non-STRUCT elements not supported.");
 
                 ptr = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
 
@@ -1026,14 +1152,12 @@ mod wire_helpers {
                 match element_size {
                     Void => {} //# Anything is a valid upgrade from Void.
                     Bit | Byte | TwoBytes | FourBytes | EightBytes => {
-                        require!(data_size >= 1, (*segment).reader,
-                                 "Existing list value is incompatible with expected type.",
-                                 continue 'use_default);
+                        require!(data_size >= 1,
+                                 "Existing list value is incompatible with expected type.");
                     }
                     Pointer => {
-                        require!(pointer_count >= 1, (*segment).reader,
-                                 "Existing list value is incompatible with expected type.",
-                                 continue 'use_default);
+                        require!(pointer_count >= 1,
+                                 "Existing list value is incompatible with expected type.");
                         //# Adjust the pointer to point at the reference segment.
                         ptr = ptr.offset(data_size as isize);
                     }
@@ -1043,7 +1167,7 @@ mod wire_helpers {
                 }
                 //# OK, looks valid.
 
-                return ListBuilder {
+                return Ok(ListBuilder {
                     marker : ::std::marker::ContravariantLifetime::<'a>,
                     segment : segment,
                     ptr : ::std::mem::transmute(ptr),
@@ -1051,23 +1175,19 @@ mod wire_helpers {
                     step : (*tag).struct_ref().word_size() * BITS_PER_WORD as u32,
                     struct_data_size : data_size as u32 * BITS_PER_WORD as u32,
                     struct_pointer_count : pointer_count
-                };
+                });
             } else {
                 let data_size = data_bits_per_element(old_size);
                 let pointer_count = pointers_per_element(old_size);
 
                 require!(data_size >= data_bits_per_element(element_size),
-                         (*segment).reader,
-                         "Existing list value is incompatible with expected type.",
-                         continue 'use_default);
+                         "Existing list value is incompatible with expected type.");
                 require!(pointer_count >= pointers_per_element(element_size),
-                         (*segment).reader,
-                         "Existing list value is incompatible with expected type.",
-                         continue 'use_default);
+                         "Existing list value is incompatible with expected type.");
 
                 let step = data_size + pointer_count * BITS_PER_POINTER as u32;
 
-                return ListBuilder {
+                return Ok(ListBuilder {
                     marker : ::std::marker::ContravariantLifetime::<'a>,
                     segment : segment,
                     ptr : ::std::mem::transmute(ptr),
@@ -1075,28 +1195,32 @@ mod wire_helpers {
                     element_count : (*reff).list_ref().element_count(),
                     struct_data_size : data_size as u32,
                     struct_pointer_count : pointer_count as u16
-                };
+                });
             }
         }
     }
 
     #[inline]
     pub unsafe fn get_writable_struct_list_pointer<'a>(orig_ref : *mut WirePointer,
-                                                       orig_segment : *mut SegmentBuilder,
+                                                       mut orig_segment : *mut SegmentBuilder,
                                                        element_size : StructSize,
-                                                       default_value : *const Word) -> ListBuilder<'a> {
-        let orig_ref_target = (*orig_ref).mut_target();
-        let mut first_time = true;
-
-        'use_default : loop {
-            if (*orig_ref).is_null() || !first_time {
-                if default_value.is_null() ||
-                    (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
-                        return ListBuilder::new_default();
-                    }
-                unimplemented!()
-            }
-            first_time = false;
+                                                       default_value : *const Word,
+                                                       orphan : *mut OrphanBuilder) -> Result<ListBuilder<'a>> {
+        if (*orig_ref).is_null() {
+            if default_value.is_null() ||
+                (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
+                    return Ok(ListBuilder::new_default());
+                }
+            //# Deep-copy the default value into the message. It is trusted, so it
+            //# is read as an unchecked segment.
+            let default_ref = ::std::mem::transmute::<*const Word,*const WirePointer>(default_value);
+            try!(copy_pointer(orig_segment, orig_ref, ::std::ptr::null(), default_ref, (*default_ref).target(),
+                              ::std::i32::MAX, false, orphan));
+        }
+
+        let orig_ref_target = builder_ref_target(orig_ref, &mut orig_segment, orphan);
+
+        {
 
             //# We must verify that the pointer has the right size and
             //# potentially upgrade it if not.
@@ -1106,9 +1230,8 @@ mod wire_helpers {
 
             let mut old_ptr = follow_builder_fars(&mut old_ref, orig_ref_target, &mut old_segment);
 
-            require!((*old_ref).kind() == WirePointerKind::List, (*old_segment).reader,
-                     "Called getList{{Field,Element}} but existing pointer is not a list.",
-                     continue 'use_default);
+            require!((*old_ref).kind() == WirePointerKind::List,
+                     "Called getList{Field,Element} but existing pointer is not a list.");
 
             let old_size = (*old_ref).list_ref().element_size();
 
@@ -1117,9 +1240,8 @@ mod wire_helpers {
 
                 let old_tag : *const WirePointer = ::std::mem::transmute(old_ptr);
                 old_ptr = old_ptr.offset(POINTER_SIZE_IN_WORDS as isize);
-                require!((*old_tag).kind() == WirePointerKind::Struct, (*old_segment).reader,
-                         "InlineComposite list with non-STRUCT elements not supported.",
-                         continue 'use_default);
+                require!((*old_tag).kind() == WirePointerKind::Struct,
+                         "InlineComposite list with non-STRUCT elements not supported.");
 
                 let old_data_size = (*old_tag).struct_ref().data_size.get();
                 let old_pointer_count = (*old_tag).struct_ref().ptr_count.get();
@@ -1128,7 +1250,7 @@ mod wire_helpers {
 
                 if old_data_size >= element_size.data && old_pointer_count >= element_size.pointers {
                     //# Old size is at least as large as we need. Ship it.
-                    return ListBuilder {
+                    return Ok(ListBuilder {
                         marker : ::std::marker::ContravariantLifetime::<'a>,
                         segment : old_segment,
                         ptr : ::std::mem::transmute(old_ptr),
@@ -1136,16 +1258,151 @@ mod wire_helpers {
                         step : old_step * BITS_PER_WORD as u32,
                         struct_data_size : old_data_size as u32 * BITS_PER_WORD as u32,
                         struct_pointer_count : old_pointer_count
-                    };
+                    });
                 }
 
                 //# The structs in this list are smaller than expected,
                 //# probably written using an older version of the
                 //# protocol. We need to make a copy and expand them.
 
-                unimplemented!();
+                let new_data_size = ::std::cmp::max(old_data_size, element_size.data);
+                let new_pointer_count = ::std::cmp::max(old_pointer_count, element_size.pointers);
+                let new_step = new_data_size as u32 + new_pointer_count as u32 * WORDS_PER_POINTER as u32;
+                let total_size64 = new_step as u64 * element_count as u64;
+                require!(total_size64 < (1 << 29),
+                         "Total size of struct list is larger than max segment size.");
+                let total_size = total_size64 as WordCount32;
+
+                let mut new_ref = orig_ref;
+                let mut new_segment = orig_segment;
+                let mut new_ptr = try!(allocate_for_upgrade(&mut new_ref, &mut new_segment,
+                                                            total_size + POINTER_SIZE_IN_WORDS as u32,
+                                                            WirePointerKind::List, orphan));
+                (*new_ref).mut_list_ref().set_inline_composite(total_size);
+
+                let new_tag : *mut WirePointer = ::std::mem::transmute(new_ptr);
+                (*new_tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct,
+                                                                            element_count);
+                (*new_tag).mut_struct_ref().set(new_data_size, new_pointer_count);
+                new_ptr = new_ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+
+                let mut src = old_ptr;
+                let mut dst = new_ptr;
+                for _ in 0..element_count {
+                    //# Copy data section.
+                    ::std::ptr::copy_nonoverlapping_memory(dst, src as *const Word,
+                                                           old_data_size as usize);
+
+                    //# Copy pointer section.
+                    let new_pointer_section : *mut WirePointer =
+                        ::std::mem::transmute(dst.offset(new_data_size as isize));
+                    let old_pointer_section : *mut WirePointer =
+                        ::std::mem::transmute(src.offset(old_data_size as isize));
+                    for j in 0..old_pointer_count as isize {
+                        try!(transfer_pointer(new_segment, new_pointer_section.offset(j),
+                                         old_segment, old_pointer_section.offset(j)));
+                    }
+
+                    dst = dst.offset(new_step as isize);
+                    src = src.offset(old_step as isize);
+                }
+
+                //# Zero out old location. See explanation in get_writable_struct_pointer().
+                //# Make sure to include the tag word.
+                ::std::ptr::zero_memory(old_ptr.offset(-(POINTER_SIZE_IN_WORDS as isize)),
+                                        (old_step * element_count) as usize + POINTER_SIZE_IN_WORDS);
+
+                return Ok(ListBuilder {
+                    marker : ::std::marker::ContravariantLifetime::<'a>,
+                    segment : new_segment,
+                    ptr : ::std::mem::transmute(new_ptr),
+                    element_count : element_count,
+                    step : new_step * BITS_PER_WORD as u32,
+                    struct_data_size : new_data_size as u32 * BITS_PER_WORD as u32,
+                    struct_pointer_count : new_pointer_count
+                });
             } else {
-                unimplemented!()
+                //# We're upgrading from a non-struct list.
+
+                let old_data_size = data_bits_per_element(old_size);
+                let old_pointer_count = pointers_per_element(old_size);
+                let old_step = old_data_size + old_pointer_count * BITS_PER_POINTER as u32;
+                let element_count = (*old_ref).list_ref().element_count();
+
+                if old_size == Void {
+                    //# Nothing to copy, just allocate a new list.
+                    return init_struct_list_pointer(orig_ref, orig_segment,
+                                                    element_count, element_size, orphan);
+                }
+
+                //# Upgrading to an inline composite list.
+
+                require!(old_size != Bit,
+                         "Found bit list where struct list was expected; upgrading boolean lists to structs is not supported.");
+
+                let mut new_data_size = element_size.data;
+                let mut new_pointer_count = element_size.pointers;
+
+                if old_size == Pointer {
+                    new_pointer_count = ::std::cmp::max(new_pointer_count, 1);
+                } else {
+                    //# Old list contains data elements, so we need at least 1 word of data.
+                    new_data_size = ::std::cmp::max(new_data_size, 1);
+                }
+
+                let new_step = new_data_size as u32 + new_pointer_count as u32 * WORDS_PER_POINTER as u32;
+                let total_words64 = element_count as u64 * new_step as u64;
+                require!(total_words64 < (1 << 29),
+                         "Total size of struct list is larger than max segment size.");
+                let total_words = total_words64 as WordCount32;
+
+                let mut new_ref = orig_ref;
+                let mut new_segment = orig_segment;
+                let mut new_ptr = try!(allocate_for_upgrade(&mut new_ref, &mut new_segment,
+                                                            total_words + POINTER_SIZE_IN_WORDS as u32,
+                                                            WirePointerKind::List, orphan));
+                (*new_ref).mut_list_ref().set_inline_composite(total_words);
+
+                let tag : *mut WirePointer = ::std::mem::transmute(new_ptr);
+                (*tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct,
+                                                                        element_count);
+                (*tag).mut_struct_ref().set(new_data_size, new_pointer_count);
+                new_ptr = new_ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+
+                if old_size == Pointer {
+                    let mut dst : *mut WirePointer =
+                        ::std::mem::transmute(new_ptr.offset(new_data_size as isize));
+                    let mut src : *mut WirePointer = ::std::mem::transmute(old_ptr);
+                    for _ in 0..element_count {
+                        try!(transfer_pointer(new_segment, dst, old_segment, src));
+                        dst = dst.offset((new_step / WORDS_PER_POINTER as u32) as isize);
+                        src = src.offset(1);
+                    }
+                } else {
+                    let mut dst : *mut u8 = ::std::mem::transmute(new_ptr);
+                    let mut src : *const u8 = ::std::mem::transmute(old_ptr);
+                    let old_byte_step = old_data_size / BITS_PER_BYTE as u32;
+                    for _ in 0..element_count {
+                        ::std::ptr::copy_nonoverlapping_memory(dst, src, old_byte_step as usize);
+                        src = src.offset(old_byte_step as isize);
+                        dst = dst.offset((new_step as usize * BYTES_PER_WORD) as isize);
+                    }
+                }
+
+                //# Zero out old location. See explanation in get_writable_struct_pointer().
+                ::std::ptr::zero_memory(old_ptr,
+                                        round_bits_up_to_words(old_step as u64 * element_count as u64)
+                                        as usize);
+
+                return Ok(ListBuilder {
+                    marker : ::std::marker::ContravariantLifetime::<'a>,
+                    segment : new_segment,
+                    ptr : ::std::mem::transmute(new_ptr),
+                    element_count : element_count,
+                    step : new_step * BITS_PER_WORD as u32,
+                    struct_data_size : new_data_size as u32 * BITS_PER_WORD as u32,
+                    struct_pointer_count : new_pointer_count
+                });
             }
         }
     }
@@ -1153,155 +1410,202 @@ mod wire_helpers {
     #[inline]
     pub unsafe fn init_text_pointer<'a>(mut reff : *mut WirePointer,
                                         mut segment : *mut SegmentBuilder,
-                                        size : ByteCount32) -> super::SegmentAnd<text::Builder<'a>> {
+                                        size : ByteCount32,
+                                        orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<text::Builder<'a>>> {
         //# The byte list must include a NUL terminator.
+        require!(size < (1 << 29) - 1, "Text blob too big.");
         let byte_size = size + 1;
 
         //# Allocate the space.
-        let ptr =
-            allocate(&mut reff, &mut segment, round_bytes_up_to_words(byte_size), WirePointerKind::List);
+        let ptr = try!(allocate(&mut reff, &mut segment, round_bytes_up_to_words(byte_size),
+                                WirePointerKind::List, orphan));
 
         //# Initialize the pointer.
         (*reff).mut_list_ref().set(Byte, byte_size);
 
-        return super::SegmentAnd {segment : segment,
-                                  value : text::Builder::new(::std::mem::transmute(ptr), size) }
+        Ok(super::SegmentAnd {segment : segment,
+                              value : text::Builder::new(::std::mem::transmute(ptr), size) })
     }
 
     #[inline]
     pub unsafe fn set_text_pointer<'a>(reff : *mut WirePointer,
                                        segment : *mut SegmentBuilder,
-                                       value : &str) -> super::SegmentAnd<text::Builder<'a>> {
+                                       value : &str,
+                                       orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<text::Builder<'a>>> {
         let value_bytes = value.as_bytes();
-        // TODO make sure the string is not longer than 2 ** 29.
-        let mut allocation = init_text_pointer(reff, segment, value_bytes.len() as u32);
+        require!(value_bytes.len() < (1 << 29), "Text blob too big.");
+        let mut allocation = try!(init_text_pointer(reff, segment, value_bytes.len() as u32, orphan));
         {
             let slice = allocation.value.borrow().as_mut_bytes();
             ::std::ptr::copy_nonoverlapping_memory(slice.as_mut_ptr(), value_bytes.as_ptr(), value_bytes.len());
         }
-        allocation
+        Ok(allocation)
     }
 
     #[inline]
     pub unsafe fn get_writable_text_pointer<'a>(mut reff : *mut WirePointer,
                                                 mut segment : *mut SegmentBuilder,
                                                 default_value : *const Word,
-                                                default_size : ByteCount32) -> text::Builder<'a> {
+                                                default_size : ByteCount32,
+                                                orphan : *mut OrphanBuilder) -> Result<text::Builder<'a>> {
         unsafe fn use_default<'a>(reff : *mut WirePointer,
                                   segment : *mut SegmentBuilder,
-                                  default_value : *const Word, default_size : ByteCount32) -> text::Builder<'a> {
+                                  default_value : *const Word, default_size : ByteCount32,
+                                  orphan : *mut OrphanBuilder) -> Result<text::Builder<'a>> {
             if default_size == 0 {
-                return text::Builder::new(::std::ptr::null_mut(), 0);
+                return Ok(text::Builder::new(::std::ptr::null_mut(), 0));
             } else {
-                let builder = init_text_pointer(reff, segment, default_size).value;
+                let builder = try!(init_text_pointer(reff, segment, default_size, orphan)).value;
                 ::std::ptr::copy_nonoverlapping_memory::<u8>(builder.as_ptr(),
                                                            ::std::mem::transmute(default_value),
                                                            default_size as usize);
-                return builder;
+                return Ok(builder);
             }
         }
 
         if (*reff).is_null() {
-            return use_default(reff, segment, default_value, default_size);
+            return use_default(reff, segment, default_value, default_size, orphan);
         }
-        let ref_target = (*reff).mut_target();
+        let ref_target = builder_ref_target(reff, &mut segment, orphan);
         let ptr = follow_builder_fars(&mut reff, ref_target, &mut segment);
 
-        require!((*reff).kind() == WirePointerKind::List, (*segment).reader,
-                "Called getText{{Field,Element}}() but existing pointer is not a list.",
-                 return use_default(reff, segment, default_value, default_size));
-        require!((*reff).list_ref().element_size() == Byte, (*segment).reader,
-                "Called getText{{Field,Element}}() but existing list pointer is not byte-sized.",
-                 return use_default(reff, segment, default_value, default_size));
+        require!((*reff).kind() == WirePointerKind::List,
+                 "Called getText{Field,Element}() but existing pointer is not a list.");
+        require!((*reff).list_ref().element_size() == Byte,
+                 "Called getText{Field,Element}() but existing list pointer is not byte-sized.");
+        require!((*reff).list_ref().element_count() > 0,
+                 "Called getText{Field,Element}() but existing text is not NUL-terminated.");
 
         //# Subtract 1 from the size for the NUL terminator.
-        return text::Builder::new(::std::mem::transmute(ptr), (*reff).list_ref().element_count() - 1);
+        return Ok(text::Builder::new(::std::mem::transmute(ptr), (*reff).list_ref().element_count() - 1));
     }
 
     #[inline]
     pub unsafe fn init_data_pointer<'a>(mut reff : *mut WirePointer,
                                         mut segment : *mut SegmentBuilder,
-                                        size : ByteCount32) -> super::SegmentAnd<data::Builder<'a>> {
+                                        size : ByteCount32,
+                                        orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<data::Builder<'a>>> {
+        require!(size < (1 << 29), "Data blob too big.");
+
         //# Allocate the space.
-        let ptr =
-            allocate(&mut reff, &mut segment, round_bytes_up_to_words(size), WirePointerKind::List);
+        let ptr = try!(allocate(&mut reff, &mut segment, round_bytes_up_to_words(size),
+                                WirePointerKind::List, orphan));
 
         //# Initialize the pointer.
         (*reff).mut_list_ref().set(Byte, size);
 
-        return super::SegmentAnd { segment : segment,
-                                   value : data::new_builder(::std::mem::transmute(ptr), size) };
+        Ok(super::SegmentAnd { segment : segment,
+                               value : data::new_builder(::std::mem::transmute(ptr), size) })
     }
 
     #[inline]
     pub unsafe fn set_data_pointer<'a>(reff : *mut WirePointer,
                                        segment : *mut SegmentBuilder,
-                                       value : &[u8]) -> super::SegmentAnd<data::Builder<'a>> {
-        let allocation = init_data_pointer(reff, segment, value.len() as u32);
+                                       value : &[u8],
+                                       orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<data::Builder<'a>>> {
+        require!(value.len() < (1 << 29), "Data blob too big.");
+        let allocation = try!(init_data_pointer(reff, segment, value.len() as u32, orphan));
         ::std::ptr::copy_nonoverlapping_memory(allocation.value.as_mut_ptr(), value.as_ptr(),
                                                value.len());
-        return allocation;
+        Ok(allocation)
     }
 
     #[inline]
     pub unsafe fn get_writable_data_pointer<'a>(mut reff : *mut WirePointer,
                                                 mut segment : *mut SegmentBuilder,
                                                 default_value : *const Word,
-                                                default_size : ByteCount32) -> data::Builder<'a> {
+                                                default_size : ByteCount32,
+                                                orphan : *mut OrphanBuilder) -> Result<data::Builder<'a>> {
         unsafe fn use_default<'a>(reff : *mut WirePointer,
                                   segment : *mut SegmentBuilder,
                                   default_value : *const Word,
-                                  default_size : ByteCount32) -> data::Builder<'a> {
+                                  default_size : ByteCount32,
+                                  orphan : *mut OrphanBuilder) -> Result<data::Builder<'a>> {
             if default_size == 0 {
-                return data::new_builder(::std::ptr::null_mut(), 0);
+                return Ok(data::new_builder(::std::ptr::null_mut(), 0));
             } else {
-                let builder = init_data_pointer(reff, segment, default_size).value;
+                let builder = try!(init_data_pointer(reff, segment, default_size, orphan)).value;
                 ::std::ptr::copy_nonoverlapping_memory::<u8>(builder.as_mut_ptr(),
                                                            ::std::mem::transmute(default_value),
                                                            default_size as usize);
-                return builder;
+                return Ok(builder);
             }
         }
 
         if (*reff).is_null() {
-            return use_default(reff, segment, default_value, default_size);
+            return use_default(reff, segment, default_value, default_size, orphan);
         }
-        let ref_target = (*reff).mut_target();
+        let ref_target = builder_ref_target(reff, &mut segment, orphan);
         let ptr = follow_builder_fars(&mut reff, ref_target, &mut segment);
 
-        require!((*reff).kind() == WirePointerKind::List, (*segment).reader,
-                 "Called getData{{Field,Element}}() but existing pointer is not a list.",
-                 return use_default(reff, segment, default_value, default_size));
-        require!((*reff).list_ref().element_size() == Byte, (*segment).reader,
-                 "Called getData{{Field,Element}}() but existing list pointer is not byte-sized.",
-                 return use_default(reff, segment, default_value, default_size));
+        require!((*reff).kind() == WirePointerKind::List,
+                 "Called getData{Field,Element}() but existing pointer is not a list.");
+        require!((*reff).list_ref().element_size() == Byte,
+                 "Called getData{Field,Element}() but existing list pointer is not byte-sized.");
 
-        return data::new_builder(::std::mem::transmute(ptr), (*reff).list_ref().element_count());
+        return Ok(data::new_builder(::std::mem::transmute(ptr), (*reff).list_ref().element_count()));
+    }
+
+    /// Returns the size of a struct once trailing zero data words and trailing null pointers
+    /// have been dropped, as required by the canonical encoding.
+    pub unsafe fn truncated_struct_size(data : *const u8, data_size : BitCount32,
+                                        pointers : *const WirePointer,
+                                        pointer_count : WirePointerCount16)
+                                        -> (WordCount32, WirePointerCount16) {
+        let mut byte_size = data_size as isize / BITS_PER_BYTE as isize;
+        while byte_size > 0 && *data.offset(byte_size - 1) == 0 {
+            byte_size -= 1;
+        }
+        let mut pointer_count = pointer_count;
+        while pointer_count > 0 && (*pointers.offset(pointer_count as isize - 1)).is_null() {
+            pointer_count -= 1;
+        }
+        (round_bytes_up_to_words(byte_size as ByteCount32), pointer_count)
     }
 
     pub unsafe fn set_struct_pointer<'a>(mut segment : *mut SegmentBuilder,
                                          mut reff : *mut WirePointer,
-                                         value : StructReader) -> super::SegmentAnd<*mut Word> {
-        let data_size : WordCount32 = round_bits_up_to_words(value.data_size as u64);
-        let total_size : WordCount32 = data_size + value.pointer_count as u32 * WORDS_PER_POINTER as u32;
+                                         value : StructReader,
+                                         canonical : bool,
+                                         orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<*mut Word>> {
+        let mut data_size : WordCount32 = round_bits_up_to_words(value.data_size as u64);
+        let mut pointer_count = value.pointer_count;
+
+        if canonical {
+            let truncated = truncated_struct_size(value.data, value.data_size,
+                                                  value.pointers, value.pointer_count);
+            pointer_count = truncated.1;
+            if value.data_size == 1 {
+                if !value.get_bool_field(0) { data_size = 0; }
+            } else {
+                data_size = truncated.0;
+            }
+        }
+
+        let total_size : WordCount32 = data_size + pointer_count as u32 * WORDS_PER_POINTER as u32;
 
-        let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::Struct);
-        (*reff).mut_struct_ref().set(data_size as u16, value.pointer_count);
+        let ptr = try!(allocate(&mut reff, &mut segment, total_size, WirePointerKind::Struct, orphan));
+        (*reff).mut_struct_ref().set(data_size as u16, pointer_count);
 
         if value.data_size == 1 {
-            *::std::mem::transmute::<*mut Word, *mut u8>(ptr) = value.get_bool_field(0) as u8
+            if data_size > 0 {
+                *::std::mem::transmute::<*mut Word, *mut u8>(ptr) = value.get_bool_field(0) as u8
+            }
         } else {
-            ::std::ptr::copy_nonoverlapping_memory::<Word>(ptr, ::std::mem::transmute(value.data),
-                                                         value.data_size as usize / BITS_PER_WORD);
+            ::std::ptr::copy_nonoverlapping_memory::<u8>(
+                ::std::mem::transmute(ptr), value.data,
+                ::std::cmp::min(value.data_size as usize / BITS_PER_BYTE,
+                                data_size as usize * BYTES_PER_WORD));
         }
 
         let pointer_section : *mut WirePointer = ::std::mem::transmute(ptr.offset(data_size as isize));
-        for i in 0..value.pointer_count as isize {
-            copy_pointer(segment, pointer_section.offset(i), value.segment, value.pointers.offset(i),
-                         value.nesting_limit);
+        for i in 0..pointer_count as isize {
+            let src = value.pointers.offset(i);
+            try!(copy_pointer(segment, pointer_section.offset(i), value.segment, src, (*src).target(),
+                              value.nesting_limit, canonical, ::std::ptr::null_mut()));
         }
 
-        super::SegmentAnd { segment : segment, value : ptr }
+        Ok(super::SegmentAnd { segment : segment, value : ptr })
     }
 
     pub unsafe fn set_capability_pointer(segment : *mut SegmentBuilder,
@@ -1312,21 +1616,24 @@ mod wire_helpers {
 
     pub unsafe fn set_list_pointer<'a>(mut segment : *mut SegmentBuilder,
                                        mut reff : *mut WirePointer,
-                                       value : ListReader) -> super::SegmentAnd<*mut Word> {
-        let total_size = round_bits_up_to_words((value.element_count * value.step) as u64);
+                                       value : ListReader,
+                                       canonical : bool,
+                                       orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<*mut Word>> {
+        let total_size = round_bits_up_to_words(value.element_count as u64 * value.step as u64);
 
-        if value.step <= BITS_PER_WORD as u32 {
+        // A struct list stays a struct list, even if its elements would fit in a primitive list.
+        if value.element_size != InlineComposite {
             //# List of non-structs.
-            let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::List);
+            let ptr = try!(allocate(&mut reff, &mut segment, total_size, WirePointerKind::List, orphan));
 
             if value.struct_pointer_count == 1 {
                 //# List of pointers.
                 (*reff).mut_list_ref().set(Pointer, value.element_count);
                 for i in 0.. value.element_count as isize {
-                    copy_pointer(segment, ::std::mem::transmute::<*mut Word,*mut WirePointer>(ptr).offset(i),
-                                 value.segment,
-                                 ::std::mem::transmute::<*const u8,*const WirePointer>(value.ptr).offset(i),
-                                 value.nesting_limit);
+                    let src = ::std::mem::transmute::<*const u8,*const WirePointer>(value.ptr).offset(i);
+                    try!(copy_pointer(segment, ::std::mem::transmute::<*mut Word,*mut WirePointer>(ptr).offset(i),
+                                      value.segment, src, (*src).target(),
+                                      value.nesting_limit, canonical, ::std::ptr::null_mut()));
                 }
             } else {
                 //# List of data.
@@ -1341,72 +1648,99 @@ mod wire_helpers {
                 };
 
                 (*reff).mut_list_ref().set(element_size, value.element_count);
-                ::std::ptr::copy_memory(ptr, ::std::mem::transmute::<*const u8,*const Word>(value.ptr), total_size as usize);
+                if canonical {
+                    // Only copy the bits that belong to elements, so that the padding at the end
+                    // of the last word is guaranteed to be zero.
+                    let total_bits = value.element_count as u64 * value.step as u64;
+                    let whole_bytes = (total_bits / BITS_PER_BYTE as u64) as usize;
+                    let dst : *mut u8 = ::std::mem::transmute(ptr);
+                    ::std::ptr::copy_nonoverlapping_memory(dst, value.ptr, whole_bytes);
+                    let leftover_bits = total_bits % BITS_PER_BYTE as u64;
+                    if leftover_bits > 0 {
+                        *dst.offset(whole_bytes as isize) =
+                            *value.ptr.offset(whole_bytes as isize) & ((1u8 << leftover_bits as usize) - 1);
+                    }
+                } else {
+                    ::std::ptr::copy_memory(ptr, ::std::mem::transmute::<*const u8,*const Word>(value.ptr),
+                                            total_size as usize);
+                }
             }
 
-            super::SegmentAnd { segment : segment, value : ptr }
+            Ok(super::SegmentAnd { segment : segment, value : ptr })
         } else {
             //# List of structs.
-            let ptr = allocate(&mut reff, &mut segment, total_size + POINTER_SIZE_IN_WORDS as u32, WirePointerKind::List);
-            (*reff).mut_list_ref().set_inline_composite(total_size);
+            let mut data_size = round_bits_up_to_words(value.struct_data_size as u64);
+            let mut pointer_count = value.struct_pointer_count;
+
+            if canonical {
+                //# Every element gets the size of the largest element, after truncation.
+                data_size = 0;
+                pointer_count = 0;
+                for i in 0..value.element_count {
+                    let src = value.ptr.offset((i as u64 * value.step as u64 / BITS_PER_BYTE as u64) as isize);
+                    let (element_data_size, element_pointer_count) =
+                        truncated_struct_size(src, value.struct_data_size,
+                                              ::std::mem::transmute(
+                                                  src.offset((value.struct_data_size / BITS_PER_BYTE as u32) as isize)),
+                                              value.struct_pointer_count);
+                    data_size = ::std::cmp::max(data_size, element_data_size);
+                    pointer_count = ::std::cmp::max(pointer_count, element_pointer_count);
+                }
+            }
 
-            let data_size = round_bits_up_to_words(value.struct_data_size as u64);
-            let pointer_count = value.struct_pointer_count;
+            let step = data_size + pointer_count as u32 * WORDS_PER_POINTER as u32;
+            let word_count = value.element_count * step;
+
+            let ptr = try!(allocate(&mut reff, &mut segment, word_count + POINTER_SIZE_IN_WORDS as u32,
+                                    WirePointerKind::List, orphan));
+            (*reff).mut_list_ref().set_inline_composite(word_count);
 
             let tag : *mut WirePointer = ::std::mem::transmute(ptr);
             (*tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct, value.element_count);
             (*tag).mut_struct_ref().set(data_size as u16, pointer_count);
             let mut dst = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
 
-            let mut src : *const Word = ::std::mem::transmute(value.ptr);
-            for _ in 0.. value.element_count {
-                ::std::ptr::copy_nonoverlapping_memory(dst, src,
-                                                     value.struct_data_size as usize / BITS_PER_WORD);
+            let src_data_bytes = value.struct_data_size / BITS_PER_BYTE as u32;
+            for i in 0.. value.element_count {
+                let src = value.ptr.offset((i as u64 * value.step as u64 / BITS_PER_BYTE as u64) as isize);
+                ::std::ptr::copy_nonoverlapping_memory::<u8>(
+                    ::std::mem::transmute(dst), src,
+                    ::std::cmp::min(src_data_bytes as usize, data_size as usize * BYTES_PER_WORD));
                 dst = dst.offset(data_size as isize);
-                src = src.offset(data_size as isize);
 
-                for _ in 0..pointer_count {
-                    copy_pointer(segment, ::std::mem::transmute(dst),
-                                 value.segment, ::std::mem::transmute(src), value.nesting_limit);
+                let src_pointers : *const WirePointer = ::std::mem::transmute(src.offset(src_data_bytes as isize));
+                for j in 0..pointer_count as isize {
+                    let src_pointer = src_pointers.offset(j);
+                    try!(copy_pointer(segment, ::std::mem::transmute(dst),
+                                      value.segment, src_pointer, (*src_pointer).target(),
+                                      value.nesting_limit, canonical, ::std::ptr::null_mut()));
                     dst = dst.offset(POINTER_SIZE_IN_WORDS as isize);
-                    src = src.offset(POINTER_SIZE_IN_WORDS as isize);
                 }
             }
-            super::SegmentAnd { segment : segment, value : ptr }
+            Ok(super::SegmentAnd { segment : segment, value : ptr })
         }
     }
 
     pub unsafe fn copy_pointer(dst_segment : *mut SegmentBuilder, dst : *mut WirePointer,
                                mut src_segment : *const SegmentReader, mut src : *const WirePointer,
-                               nesting_limit : i32) -> super::SegmentAnd<*mut Word> {
-
-        unsafe fn use_default(dst_segment : *mut SegmentBuilder, dst : *mut WirePointer)
-            -> super::SegmentAnd<*mut Word> {
-                ::std::ptr::zero_memory(dst, 1);
-                return super::SegmentAnd { segment : dst_segment, value : ::std::ptr::null_mut() };
-            }
-
-        let src_target = (*src).target();
+                               src_target : *const Word, nesting_limit : i32, canonical : bool,
+                               orphan : *mut OrphanBuilder) -> Result<super::SegmentAnd<*mut Word>> {
 
         if (*src).is_null() {
-            return use_default(dst_segment, dst);
+            ::std::ptr::zero_memory(dst, 1);
+            return Ok(super::SegmentAnd { segment : dst_segment, value : ::std::ptr::null_mut() });
         }
 
-        let mut ptr = follow_fars(&mut src, src_target, &mut src_segment);
-        if ptr.is_null() {
-            return use_default(dst_segment, dst);
-        }
+        let mut ptr = try!(follow_fars(&mut src, src_target, &mut src_segment));
 
         match (*src).kind() {
             WirePointerKind::Struct => {
-                require!(nesting_limit > 0, *src_segment,
-                        "Message is too deeply-nested or contains cycles.  See ReaderOptions.",
-                         return use_default(dst_segment, dst));
+                require!(nesting_limit > 0,
+                         "Message is too deeply-nested or contains cycles.  See ReaderOptions.");
 
                 require!(bounds_check(src_segment, ptr, ptr.offset((*src).struct_ref().word_size() as isize)),
-                         *src_segment,
-                        "Message contains out-of-bounds struct pointer.",
-                         return use_default(dst_segment, dst));
+                         "Message contains out-of-bounds struct pointer.");
+                try!(amplified_read(src_segment, (*src).struct_ref().word_size() as u64));
 
                 return set_struct_pointer(
                     dst_segment, dst,
@@ -1417,14 +1751,15 @@ mod wire_helpers {
                         pointers : ::std::mem::transmute(ptr.offset((*src).struct_ref().data_size.get() as isize)),
                         data_size : (*src).struct_ref().data_size.get() as u32 * BITS_PER_WORD as u32,
                         pointer_count : (*src).struct_ref().ptr_count.get(),
-                        nesting_limit : nesting_limit - 1 });
+                        bit0_offset : 0,
+                        nesting_limit : nesting_limit - 1 },
+                    canonical, orphan);
 
             }
             WirePointerKind::List => {
                 let element_size = (*src).list_ref().element_size();
-                require!(nesting_limit > 0, *src_segment,
-                        "Message is too deeply-nested or contains cycles. See ReaderOptions.",
-                         return use_default(dst_segment, dst));
+                require!(nesting_limit > 0,
+                         "Message is too deeply-nested or contains cycles. See ReaderOptions.");
 
                 if element_size == InlineComposite {
                     let word_count = (*src).list_ref().inline_composite_word_count();
@@ -1432,22 +1767,24 @@ mod wire_helpers {
                     ptr = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
 
                     require!(bounds_check(src_segment, ptr.offset(-1), ptr.offset(word_count as isize)),
-                             *src_segment,
-                             "Message contains out-of-bounds list pointer.",
-                             return use_default(dst_segment, dst));
+                             "Message contains out-of-bounds list pointer.");
 
                     require!((*tag).kind() == WirePointerKind::Struct,
-                             *src_segment,
-                             "InlineComposite lists of non-STRUCT type are not supported.",
-                             return use_default(dst_segment, dst));
+                             "InlineComposite lists of non-STRUCT type are not supported.");
 
                     let element_count = (*tag).inline_composite_list_element_count();
                     let words_per_element = (*tag).struct_ref().word_size();
 
-                    require!(words_per_element * element_count <= word_count,
-                             *src_segment,
-                             "InlineComposite list's elements overrun its word count.",
-                             return use_default(dst_segment, dst));
+                    require!(words_per_element as u64 * element_count as u64 <= word_count as u64,
+                             "InlineComposite list's elements overrun its word count.");
+
+                    try!(amplified_read(src_segment, word_count as u64 + POINTER_SIZE_IN_WORDS as u64));
+                    if words_per_element == 0 {
+                        //# Watch out for lists of zero-sized structs, which can claim to be
+                        //# arbitrarily large without having sent actual data.
+                        try!(amplified_read(src_segment, element_count as u64));
+                    }
+
                     return set_list_pointer(
                         dst_segment, dst,
                         ListReader {
@@ -1458,8 +1795,10 @@ mod wire_helpers {
                             step : words_per_element * BITS_PER_WORD as u32,
                             struct_data_size : (*tag).struct_ref().data_size.get() as u32 * BITS_PER_WORD as u32,
                             struct_pointer_count : (*tag).struct_ref().ptr_count.get(),
+                            element_size : InlineComposite,
                             nesting_limit : nesting_limit - 1
-                        })
+                        },
+                        canonical, orphan)
                 } else {
                     let data_size = data_bits_per_element(element_size);
                     let pointer_count = pointers_per_element(element_size);
@@ -1468,9 +1807,15 @@ mod wire_helpers {
                     let word_count = round_bits_up_to_words(element_count as u64 * step as u64);
 
                     require!(bounds_check(src_segment, ptr, ptr.offset(word_count as isize)),
-                             *src_segment,
-                             "Message contains out-of-bounds list pointer.",
-                             return use_default(dst_segment, dst));
+                             "Message contains out-of-bounds list pointer.");
+
+                    if element_size == Void {
+                        //# Watch out for lists of void, which can claim to be arbitrarily large
+                        //# without having sent actual data.
+                        try!(amplified_read(src_segment, element_count as u64));
+                    } else {
+                        try!(amplified_read(src_segment, word_count as u64));
+                    }
 
                     return set_list_pointer(
                         dst_segment, dst,
@@ -1482,25 +1827,179 @@ mod wire_helpers {
                             step : step,
                             struct_data_size : data_size as u32,
                             struct_pointer_count : pointer_count as u16,
+                            element_size : element_size,
                             nesting_limit : nesting_limit - 1
-                        })
+                        },
+                        canonical, orphan)
                 }
             }
             WirePointerKind::Far => {
-                panic!("Far pointer should have been handled above");
+                return Err(Error::new_decode_error("Malformed double-far pointer.", None));
             }
             WirePointerKind::Other => {
-                require!((*src).is_capability(), *src_segment, "Unknown pointer type.",
-                         return use_default(dst_segment, dst));
+                require!((*src).is_capability(), "Unknown pointer type.");
+                require!(!canonical, "Cannot create a canonical message with a capability.");
+                // Default values are copied from outside of any message, so they have no cap
+                // table that an index could refer to.
+                require!(!src_segment.is_null(), "Default values cannot contain capabilities.");
+                // Re-inject the capability into the destination message, whose cap table indexes
+                // are unrelated to the source's.
                 match (*src_segment).arena.extract_cap((*src).cap_ref().index.get() as usize) {
                     Some(cap) => {
                         set_capability_pointer(dst_segment, dst, cap);
-                        return super::SegmentAnd { segment : dst_segment, value : ::std::ptr::null_mut() };
+                        return Ok(super::SegmentAnd { segment : dst_segment, value : ::std::ptr::null_mut() });
                     }
                     None => {
-                        require_panic!(*src_segment,
-                                      "Message contained invalid capability pointer.",
-                                      return use_default(dst_segment, dst));
+                        return Err(Error::new_decode_error(
+                            "Message contained invalid capability pointer.", None));
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn is_nonzero_word(ptr : *const Word) -> bool {
+        *::std::mem::transmute::<*const Word, *const u64>(ptr) != 0
+    }
+
+    /// Checks that the object `reff` points at is laid out exactly as canonicalization would lay
+    /// it out, starting at `*read_head`. On success, `*read_head` is advanced past the object and
+    /// everything it points at.
+    pub unsafe fn is_canonical(segment : *const SegmentReader, reff : *const WirePointer,
+                               read_head : &mut *const Word, nesting_limit : i32) -> Result<bool> {
+        if (*reff).is_null() { return Ok(true); }
+
+        require!(nesting_limit > 0, "Message is too deeply nested.");
+
+        match (*reff).kind() {
+            WirePointerKind::Far | WirePointerKind::Other => Ok(false),
+            WirePointerKind::Struct => {
+                let data_size = (*reff).struct_ref().data_size.get();
+                let pointer_count = (*reff).struct_ref().ptr_count.get();
+
+                // A zero-sized struct takes up no space, so its offset doesn't say where it is.
+                // Canonicalization encodes it with an offset of -1.
+                if data_size == 0 && pointer_count == 0 { return Ok(true); }
+
+                let ptr = (*reff).target();
+                if ptr != *read_head { return Ok(false); }
+
+                let word_size = (*reff).struct_ref().word_size();
+                require!(bounds_check(segment, ptr, ptr.offset(word_size as isize)),
+                         "Message contains out-of-bounds struct pointer.");
+                *read_head = ptr.offset(word_size as isize);
+
+                let pointer_section : *const WirePointer =
+                    ::std::mem::transmute(ptr.offset(data_size as isize));
+
+                //# The last data word and the last pointer must not be zero, or else the struct
+                //# should have been truncated.
+                if data_size > 0 && !is_nonzero_word(ptr.offset(data_size as isize - 1)) {
+                    return Ok(false);
+                }
+                if pointer_count > 0 && (*pointer_section.offset(pointer_count as isize - 1)).is_null() {
+                    return Ok(false);
+                }
+
+                for i in 0..pointer_count as isize {
+                    if !try!(is_canonical(segment, pointer_section.offset(i), read_head, nesting_limit - 1)) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            WirePointerKind::List => {
+                let ptr = (*reff).target();
+                if ptr != *read_head { return Ok(false); }
+
+                let element_size = (*reff).list_ref().element_size();
+                match element_size {
+                    Void => Ok(true),
+                    Bit | Byte | TwoBytes | FourBytes | EightBytes => {
+                        let total_bits = (*reff).list_ref().element_count() as u64 *
+                            data_bits_per_element(element_size) as u64;
+                        let word_count = round_bits_up_to_words(total_bits);
+                        require!(bounds_check(segment, ptr, ptr.offset(word_count as isize)),
+                                 "Message contains out-of-bounds list pointer.");
+                        *read_head = ptr.offset(word_count as isize);
+
+                        //# Padding at the end of the last word must be zero.
+                        let bytes : *const u8 = ::std::mem::transmute(ptr);
+                        let whole_bytes = total_bits / BITS_PER_BYTE as u64;
+                        let leftover_bits = total_bits % BITS_PER_BYTE as u64;
+                        let mut padding_start = whole_bytes;
+                        if leftover_bits > 0 {
+                            if *bytes.offset(whole_bytes as isize) >> leftover_bits as usize != 0 {
+                                return Ok(false);
+                            }
+                            padding_start += 1;
+                        }
+                        for i in padding_start..(word_count as u64 * BYTES_PER_WORD as u64) {
+                            if *bytes.offset(i as isize) != 0 { return Ok(false); }
+                        }
+                        Ok(true)
+                    }
+                    Pointer => {
+                        let count = (*reff).list_ref().element_count();
+                        require!(bounds_check(segment, ptr, ptr.offset(count as isize * WORDS_PER_POINTER as isize)),
+                                 "Message contains out-of-bounds list pointer.");
+                        *read_head = ptr.offset(count as isize * WORDS_PER_POINTER as isize);
+
+                        let pointers : *const WirePointer = ::std::mem::transmute(ptr);
+                        for i in 0..count as isize {
+                            if !try!(is_canonical(segment, pointers.offset(i), read_head, nesting_limit - 1)) {
+                                return Ok(false);
+                            }
+                        }
+                        Ok(true)
+                    }
+                    InlineComposite => {
+                        let word_count = (*reff).list_ref().inline_composite_word_count();
+                        require!(bounds_check(segment, ptr,
+                                              ptr.offset(word_count as isize + POINTER_SIZE_IN_WORDS as isize)),
+                                 "Message contains out-of-bounds list pointer.");
+                        *read_head = ptr.offset(word_count as isize + POINTER_SIZE_IN_WORDS as isize);
+
+                        let tag : *const WirePointer = ::std::mem::transmute(ptr);
+                        require!((*tag).kind() == WirePointerKind::Struct,
+                                 "InlineComposite lists of non-STRUCT type are not supported.");
+                        let element_count = (*tag).inline_composite_list_element_count();
+                        let data_size = (*tag).struct_ref().data_size.get();
+                        let pointer_count = (*tag).struct_ref().ptr_count.get();
+                        let step = (*tag).struct_ref().word_size();
+
+                        if step as u64 * element_count as u64 != word_count as u64 {
+                            return Ok(false);
+                        }
+                        if step == 0 { return Ok(true); }
+
+                        //# At least one element must use the last data word and the last pointer,
+                        //# or else the elements should have been truncated.
+                        let elements = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+                        let mut data_truncated = data_size == 0;
+                        let mut pointers_truncated = pointer_count == 0;
+                        for i in 0..element_count as isize {
+                            let element = elements.offset(i * step as isize);
+                            if data_size > 0 && is_nonzero_word(element.offset(data_size as isize - 1)) {
+                                data_truncated = true;
+                            }
+                            if pointer_count > 0 &&
+                                is_nonzero_word(element.offset(data_size as isize + pointer_count as isize - 1)) {
+                                pointers_truncated = true;
+                            }
+                        }
+                        if !data_truncated || !pointers_truncated { return Ok(false); }
+
+                        for i in 0..element_count as isize {
+                            let pointers : *const WirePointer =
+                                ::std::mem::transmute(elements.offset(i * step as isize + data_size as isize));
+                            for j in 0..pointer_count as isize {
+                                if !try!(is_canonical(segment, pointers.offset(j), read_head, nesting_limit - 1)) {
+                                    return Ok(false);
+                                }
+                            }
+                        }
+                        Ok(true)
                     }
                 }
             }
@@ -1510,49 +2009,43 @@ mod wire_helpers {
     #[inline]
     pub unsafe fn read_struct_pointer<'a>(mut segment: *const SegmentReader,
                                           mut reff : *const WirePointer,
+                                          mut ref_target : *const Word,
                                           default_value : *const Word,
-                                          nesting_limit : i32) -> StructReader<'a> {
-        let ref_target : *const Word = (*reff).target();
-        let mut first_time = true;
-
-        'use_default : loop {
-            if (*reff).is_null() || !first_time {
-                if default_value.is_null() ||
-                    (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
-                        return StructReader::new_default();
-                    }
-                //segment = ::std::ptr::null();
-                //reff = ::std::mem::transmute::<*Word,*WirePointer>(default_value);
-                unimplemented!()
-            }
-            first_time = true;
+                                          nesting_limit : i32) -> Result<StructReader<'a>> {
+        if (*reff).is_null() {
+            if default_value.is_null() ||
+                (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
+                    return Ok(StructReader::new_default());
+                }
+            //# The default value is trusted, so read it as an unchecked segment.
+            segment = ::std::ptr::null();
+            reff = ::std::mem::transmute::<*const Word,*const WirePointer>(default_value);
+            ref_target = (*reff).target();
+        }
 
-            require!(nesting_limit > 0, *segment, "Message is too deeply-nested or contains cycles.",
-                     continue 'use_default);
+        require!(nesting_limit > 0, "Message is too deeply-nested or contains cycles.");
 
-            let ptr = follow_fars(&mut reff, ref_target, &mut segment);
+        let ptr = try!(follow_fars(&mut reff, ref_target, &mut segment));
 
-            let data_size_words = (*reff).struct_ref().data_size.get();
+        let data_size_words = (*reff).struct_ref().data_size.get();
 
-            require!((*reff).kind() == WirePointerKind::Struct, *segment,
-                     "Message contains non-struct pointer where struct pointer was expected.",
-                     continue 'use_default);
+        require!((*reff).kind() == WirePointerKind::Struct,
+                 "Message contains non-struct pointer where struct pointer was expected.");
 
-            require!(bounds_check(segment, ptr,
-                                  ptr.offset((*reff).struct_ref().word_size() as isize)),
-                     *segment,
-                     "Message contains out-of-bounds struct pointer.",
-                     continue 'use_default);
+        require!(bounds_check(segment, ptr,
+                              ptr.offset((*reff).struct_ref().word_size() as isize)),
+                 "Message contains out-of-bounds struct pointer.");
+        try!(amplified_read(segment, (*reff).struct_ref().word_size() as u64));
 
-            return StructReader {
-                marker : ::std::marker::ContravariantLifetime::<'a>,
-                segment : segment,
-                data : ::std::mem::transmute(ptr),
-                pointers : ::std::mem::transmute(ptr.offset(data_size_words as isize)),
-                data_size : data_size_words as u32 * BITS_PER_WORD as BitCount32,
-                pointer_count : (*reff).struct_ref().ptr_count.get(),
-                nesting_limit : nesting_limit - 1 };
-        }
+        Ok(StructReader {
+            marker : ::std::marker::ContravariantLifetime::<'a>,
+            segment : segment,
+            data : ::std::mem::transmute(ptr),
+            pointers : ::std::mem::transmute(ptr.offset(data_size_words as isize)),
+            data_size : data_size_words as u32 * BITS_PER_WORD as BitCount32,
+            pointer_count : (*reff).struct_ref().ptr_count.get(),
+            bit0_offset : 0,
+            nesting_limit : nesting_limit - 1 })
      }
 
     #[inline]
@@ -1560,15 +2053,18 @@ mod wire_helpers {
                                           reff : *const WirePointer,
                                           _nesting_limit : i32) -> Box<ClientHook+Send> {
         if (*reff).is_null() {
-            panic!("broken cap factory is unimplemented");
+            new_broken_cap("Calling capability extracted from a null pointer.")
         } else if !(*reff).is_capability() {
-            panic!("Message contains non-capability pointer where capability pointer was expected.");
+            new_broken_cap("Calling capability extracted from a non-capability pointer.")
+        } else if segment.is_null() {
+            // An unchecked message, such as a default value, has no cap table.
+            new_broken_cap("Calling capability extracted from a message without a cap table.")
         } else {
             let n = (*reff).cap_ref().index.get() as usize;
             match (*segment).arena.extract_cap(n) {
                 Some(client_hook) => { client_hook }
                 None => {
-                    panic!("Message contains invalid capability pointer: {}", n)
+                    new_broken_cap("Calling invalid capability pointer.")
                 }
             }
         }
@@ -1576,151 +2072,147 @@ mod wire_helpers {
 
     #[inline]
     pub unsafe fn read_list_pointer<'a>(mut segment: *const SegmentReader,
-                                      mut reff : *const WirePointer,
-                                      default_value : *const Word,
-                                      expected_element_size : ElementSize,
-                                      nesting_limit : i32) -> ListReader<'a> {
-        let ref_target : *const Word = (*reff).target();
-        let mut first_time = true;
-
-        'use_default : loop {
-            if (*reff).is_null() || !first_time {
-                if default_value.is_null() ||
-                    (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
-                        return ListReader::new_default();
-                    }
-                panic!("list default values unimplemented");
-            }
-            first_time = false;
+                                        mut reff : *const WirePointer,
+                                        mut ref_target : *const Word,
+                                        default_value : *const Word,
+                                        expected_element_size : ElementSize,
+                                        nesting_limit : i32) -> Result<ListReader<'a>> {
+        if (*reff).is_null() {
+            if default_value.is_null() ||
+                (*::std::mem::transmute::<*const Word,*const WirePointer>(default_value)).is_null() {
+                    return Ok(ListReader::new_default());
+                }
+            //# The default value is trusted, so read it as an unchecked segment.
+            segment = ::std::ptr::null();
+            reff = ::std::mem::transmute::<*const Word,*const WirePointer>(default_value);
+            ref_target = (*reff).target();
+        }
 
-            require!(nesting_limit > 0, *segment, "nesting limit exceeded", continue 'use_default);
+        require!(nesting_limit > 0, "Message is too deeply-nested or contains cycles.");
 
-            let mut ptr : *const Word = follow_fars(&mut reff, ref_target, &mut segment);
+        let mut ptr : *const Word = try!(follow_fars(&mut reff, ref_target, &mut segment));
 
-            require!((*reff).kind() == WirePointerKind::List, *segment,
-                     "Message contains non-list pointer where list pointer was expected",
-                     continue 'use_default);
+        require!((*reff).kind() == WirePointerKind::List,
+                 "Message contains non-list pointer where list pointer was expected");
 
-            let list_ref = (*reff).list_ref();
+        let list_ref = (*reff).list_ref();
 
-            match list_ref.element_size() {
-                InlineComposite => {
-                    let word_count = list_ref.inline_composite_word_count();
+        match list_ref.element_size() {
+            InlineComposite => {
+                let word_count = list_ref.inline_composite_word_count();
 
-                    let tag: *const WirePointer = ::std::mem::transmute(ptr);
+                let tag: *const WirePointer = ::std::mem::transmute(ptr);
 
-                    ptr = ptr.offset(1);
+                ptr = ptr.offset(1);
 
-                    require!(bounds_check(segment, ptr.offset(-1),
-                                          ptr.offset(word_count as isize)),
-                             *segment,
-                             "Message contains out-of-bounds list pointer",
-                             continue 'use_default);
+                require!(bounds_check(segment, ptr.offset(-1),
+                                      ptr.offset(word_count as isize)),
+                         "Message contains out-of-bounds list pointer");
 
-                    require!((*tag).kind() == WirePointerKind::Struct,
-                             *segment,
-                             "InlineComposite lists of non-STRUCT type are not supported",
-                             continue 'use_default);
-
-                    let size = (*tag).inline_composite_list_element_count();
-                    let struct_ref = (*tag).struct_ref();
-                    let words_per_element = struct_ref.word_size();
-
-                    require!(size * words_per_element <= word_count,
-                             *segment,
-                             "InlineComposite list's elements overrun its word count",
-                             continue 'use_default);
-
-                    //# If a struct list was not expected, then presumably
-                    //# a non-struct list was upgraded to a struct list.
-                    //# We need to manipulate the pointer to point at the
-                    //# first field of the struct. Together with the
-                    //# "stepBits", this will allow the struct list to be
-                    //# accessed as if it were a primitive list without
-                    //# branching.
-
-                    //# Check whether the size is compatible.
-                    match expected_element_size {
-                        Void => {}
-                        Bit |
-                        Byte | TwoBytes | FourBytes | EightBytes => {
-                            require!(struct_ref.data_size.get() > 0,
-                                     *segment,
-                                    "Expected a primitive list, but got a list of pointer-only structs",
-                                     continue 'use_default);
-                        }
-                        Pointer => {
-                            ptr = ptr.offset(struct_ref.data_size.get() as isize);
-                            require!(struct_ref.ptr_count.get() > 0,
-                                     *segment,
-                                     "Expected a pointer list, but got a list of data-only structs",
-                                     continue 'use_default);
-                        }
-                        InlineComposite => {}
-                    }
+                require!((*tag).kind() == WirePointerKind::Struct,
+                         "InlineComposite lists of non-STRUCT type are not supported");
 
-                    return ListReader {
-                        marker : ::std::marker::ContravariantLifetime::<'a>,
-                        segment : segment,
-                        ptr : ::std::mem::transmute(ptr),
-                        element_count : size,
-                        step : words_per_element * BITS_PER_WORD as u32,
-                        struct_data_size : struct_ref.data_size.get() as u32 * (BITS_PER_WORD as u32),
-                        struct_pointer_count : struct_ref.ptr_count.get() as u16,
-                        nesting_limit : nesting_limit - 1
-                    };
+                let size = (*tag).inline_composite_list_element_count();
+                let struct_ref = (*tag).struct_ref();
+                let words_per_element = struct_ref.word_size();
+
+                require!(size as u64 * words_per_element as u64 <= word_count as u64,
+                         "InlineComposite list's elements overrun its word count");
+
+                try!(amplified_read(segment, word_count as u64 + POINTER_SIZE_IN_WORDS as u64));
+                if words_per_element == 0 {
+                    //# Watch out for lists of zero-sized structs, which can claim to be
+                    //# arbitrarily large without having sent actual data.
+                    try!(amplified_read(segment, size as u64));
                 }
-                _ => {
-
-                    //# This is a primitive or pointer list, but all such
-                    //# lists can also be interpreted as struct lists. We
-                    //# need to compute the data size and pointer count for
-                    //# such structs.
-                    let data_size = data_bits_per_element(list_ref.element_size());
-                    let pointer_count = pointers_per_element(list_ref.element_size());
-                    let step = data_size + pointer_count * BITS_PER_POINTER as u32;
 
-                    require!(
-                        bounds_check(
-                            segment, ptr,
-                            ptr.offset(
-                                round_bits_up_to_words(
-                                    (list_ref.element_count() * step) as u64) as isize)),
-                        *segment,
-                        "Message contains out-of-bounds list pointer.",
-                        continue 'use_default);
-
-                    //# Verify that the elements are at least as large as
-                    //# the expected type. Note that if we expected
-                    //# InlineComposite, the expected sizes here will be
-                    //# zero, because bounds checking will be performed at
-                    //# field access time. So this check here is for the
-                    //# case where we expected a list of some primitive or
-                    //# pointer type.
-
-                    let expected_data_bits_per_element =
-                        data_bits_per_element(expected_element_size);
-                    let expected_pointers_per_element =
-                        pointers_per_element(expected_element_size);
-
-                    require!(expected_data_bits_per_element <= data_size, *segment,
-                             "Message contains list with incompatible element type.",
-                             continue 'use_default);
-                    require!(expected_pointers_per_element <= pointer_count, *segment,
-                             "Message contains list with incompatible element type",
-                             continue 'use_default);
-
-                    return ListReader {
-                        marker : ::std::marker::ContravariantLifetime::<'a>,
-                        segment : segment,
-                        ptr : ::std::mem::transmute(ptr),
-                        element_count : list_ref.element_count(),
-                        step : step,
-                        struct_data_size : data_size as u32,
-                        struct_pointer_count : pointer_count as u16,
-                        nesting_limit : nesting_limit - 1
-                    };
+                //# If a struct list was not expected, then presumably
+                //# a non-struct list was upgraded to a struct list.
+                //# We need to manipulate the pointer to point at the
+                //# first field of the struct. Together with the
+                //# "stepBits", this will allow the struct list to be
+                //# accessed as if it were a primitive list without
+                //# branching.
+
+                //# Check whether the size is compatible.
+                match expected_element_size {
+                    Void => {}
+                    Bit |
+                    Byte | TwoBytes | FourBytes | EightBytes => {
+                        require!(struct_ref.data_size.get() > 0,
+                                 "Expected a primitive list, but got a list of pointer-only structs");
+                    }
+                    Pointer => {
+                        ptr = ptr.offset(struct_ref.data_size.get() as isize);
+                        require!(struct_ref.ptr_count.get() > 0,
+                                 "Expected a pointer list, but got a list of data-only structs");
+                    }
+                    InlineComposite => {}
                 }
+
+                Ok(ListReader {
+                    marker : ::std::marker::ContravariantLifetime::<'a>,
+                    segment : segment,
+                    ptr : ::std::mem::transmute(ptr),
+                    element_count : size,
+                    step : words_per_element * BITS_PER_WORD as u32,
+                    struct_data_size : struct_ref.data_size.get() as u32 * (BITS_PER_WORD as u32),
+                    struct_pointer_count : struct_ref.ptr_count.get() as u16,
+                    element_size : InlineComposite,
+                    nesting_limit : nesting_limit - 1
+                })
+            }
+            _ => {
+
+                //# This is a primitive or pointer list, but all such
+                //# lists can also be interpreted as struct lists. We
+                //# need to compute the data size and pointer count for
+                //# such structs.
+                let data_size = data_bits_per_element(list_ref.element_size());
+                let pointer_count = pointers_per_element(list_ref.element_size());
+                let step = data_size + pointer_count * BITS_PER_POINTER as u32;
+                let word_count = round_bits_up_to_words(list_ref.element_count() as u64 * step as u64);
+
+                require!(bounds_check(segment, ptr, ptr.offset(word_count as isize)),
+                         "Message contains out-of-bounds list pointer.");
+
+                if list_ref.element_size() == Void {
+                    //# Watch out for lists of void, which can claim to be arbitrarily large
+                    //# without having sent actual data.
+                    try!(amplified_read(segment, list_ref.element_count() as u64));
+                } else {
+                    try!(amplified_read(segment, word_count as u64));
+                }
+
+                //# Verify that the elements are at least as large as
+                //# the expected type. Note that if we expected
+                //# InlineComposite, the expected sizes here will be
+                //# zero, because bounds checking will be performed at
+                //# field access time. So this check here is for the
+                //# case where we expected a list of some primitive or
+                //# pointer type.
+
+                let expected_data_bits_per_element =
+                    data_bits_per_element(expected_element_size);
+                let expected_pointers_per_element =
+                    pointers_per_element(expected_element_size);
+
+                require!(expected_data_bits_per_element <= data_size,
+                         "Message contains list with incompatible element type.");
+                require!(expected_pointers_per_element <= pointer_count,
+                         "Message contains list with incompatible element type.");
+
+                Ok(ListReader {
+                    marker : ::std::marker::ContravariantLifetime::<'a>,
+                    segment : segment,
+                    ptr : ::std::mem::transmute(ptr),
+                    element_count : list_ref.element_count(),
+                    step : step,
+                    struct_data_size : data_size as u32,
+                    struct_pointer_count : pointer_count as u16,
+                    element_size : list_ref.element_size(),
+                    nesting_limit : nesting_limit - 1
+                })
             }
         }
     }
@@ -1728,107 +2220,388 @@ mod wire_helpers {
     #[inline]
     pub unsafe fn read_text_pointer<'a>(mut segment : *const SegmentReader,
                                         mut reff : *const WirePointer,
+                                        ref_target : *const Word,
                                         default_value : *const Word,
                                         default_size : ByteCount32
-                                        ) -> text::Reader<'a> {
-        unsafe fn use_default<'a>(default_value : *const Word, default_size : ByteCount32) -> text::Reader<'a> {
-            //   TODO?       if default_value.is_null() { default_value = &"" }
-
-            // assume that the default value is valid utf-8.
-            return text::new_reader(::std::mem::transmute(default_value), default_size).unwrap();
-        }
-
+                                        ) -> Result<text::Reader<'a>> {
         if (*reff).is_null() {
-            return use_default(default_value, default_size);
+            // Assume that the default value is valid utf-8.
+            return Ok(text::new_reader(::std::mem::transmute(default_value), default_size).unwrap());
         }
 
-        let ref_target = (*reff).target();
-
-        let ptr : *const Word = follow_fars(&mut reff, ref_target, &mut segment);
+        let ptr : *const Word = try!(follow_fars(&mut reff, ref_target, &mut segment));
 
         let list_ref = (*reff).list_ref();
 
         let size = list_ref.element_count();
 
-        require!((*reff).kind() == WirePointerKind::List, *segment,
-                 "Message contains non-list pointer where text was expected",
-                 return use_default(default_value, default_size));
+        require!((*reff).kind() == WirePointerKind::List,
+                 "Message contains non-list pointer where text was expected.");
 
-        require!(list_ref.element_size() == Byte, *segment,
-                 "Message contains list pointer of non-bytes where text was expected.",
-                 return use_default(default_value, default_size));
+        require!(list_ref.element_size() == Byte,
+                 "Message contains list pointer of non-bytes where text was expected.");
 
         require!(bounds_check(segment, ptr,
                               ptr.offset(round_bytes_up_to_words(size) as isize)),
-                 *segment,
-                 "Message contains out-of-bounds text pointer.",
-                 return use_default(default_value, default_size));
+                 "Message contains out-of-bounds text pointer.");
+        try!(amplified_read(segment, round_bytes_up_to_words(size) as u64));
 
-        require!(size > 0, *segment, "Message contains text that is not NUL-terminated",
-                 return use_default(default_value, default_size));
+        require!(size > 0, "Message contains text that is not NUL-terminated.");
 
         let str_ptr = ::std::mem::transmute::<*const Word,*const u8>(ptr);
 
-        require!((*str_ptr.offset((size - 1) as isize)) == 0u8, *segment,
-                 "Message contains text that is not NUL-terminated",
-                 return use_default(default_value, default_size));
+        require!((*str_ptr.offset((size - 1) as isize)) == 0u8,
+                 "Message contains text that is not NUL-terminated.");
 
         match text::new_reader(str_ptr, size-1) {
-            Ok(t) => return t,
-            Err(_) => require_panic!(*segment,
-                                     "Text contains non-utf8 data",
-                                     return use_default(default_value, default_size)),
+            Ok(t) => Ok(t),
+            Err(e) => Err(Error::new_decode_error("Text contains non-utf8 data.",
+                                                  Some(format!("{:?}", e)))),
         }
     }
 
     #[inline]
     pub unsafe fn read_data_pointer<'a>(mut segment : *const SegmentReader,
                                         mut reff : *const WirePointer,
+                                        ref_target : *const Word,
                                         default_value : *const Word,
                                         default_size : ByteCount32
-                                        ) -> data::Reader<'a> {
-        unsafe fn use_default<'a>(default_value : *const Word, default_size : ByteCount32) -> data::Reader<'a> {
-            return data::new_reader(::std::mem::transmute(default_value), default_size);
-        }
-
+                                        ) -> Result<data::Reader<'a>> {
         if (*reff).is_null() {
-            return use_default(default_value, default_size);
+            return Ok(data::new_reader(::std::mem::transmute(default_value), default_size));
         }
 
-        let ref_target = (*reff).target();
-
-        let ptr : *const Word = follow_fars(&mut reff, ref_target, &mut segment);
+        let ptr : *const Word = try!(follow_fars(&mut reff, ref_target, &mut segment));
 
         let list_ref = (*reff).list_ref();
 
         let size : u32 = list_ref.element_count();
 
-        require!((*reff).kind() == WirePointerKind::List, *segment,
-                 "Message contains non-list pointer where data was expected",
-                 return use_default(default_value, default_size));
+        require!((*reff).kind() == WirePointerKind::List,
+                 "Message contains non-list pointer where data was expected.");
 
-        require!(list_ref.element_size() == Byte, *segment,
-                 "Message contains list pointer of non-bytes where data was expected",
-                 return use_default(default_value, default_size));
+        require!(list_ref.element_size() == Byte,
+                 "Message contains list pointer of non-bytes where data was expected.");
 
         require!(bounds_check(segment, ptr,
                               ptr.offset(round_bytes_up_to_words(size) as isize)),
-                 *segment,
-                 "Message contains out-of-bounds data pointer.",
-                 return use_default(default_value, default_size));
+                 "Message contains out-of-bounds data pointer.");
+        try!(amplified_read(segment, round_bytes_up_to_words(size) as u64));
+
+        Ok(data::new_reader(::std::mem::transmute(ptr), size))
+    }
+
+    pub struct Validator<'a> {
+        pub path : Vec<PathElement>,
+        pub problems : Vec<Problem>,
+        pub words_remaining : WordCount64,
+        pub exhausted : bool,
+
+        // Where the caller expects text.
+        pub text_paths : &'a [&'a [PathPattern]],
+    }
+
+    impl <'a> Validator<'a> {
+        pub fn report(&mut self, description : &'static str, detail : Option<String>) {
+            self.problems.push(Problem { path : self.path.clone(),
+                                         description : description,
+                                         detail : detail });
+        }
+
+        fn report_error(&mut self, error : Error) {
+            match error {
+                Error::Decode { description, detail } => self.report(description, detail),
+                Error::ResourceLimit { description, detail } => self.report(description, detail),
+                Error::Io(e) => self.report("I/O error during validation.", Some(format!("{}", e))),
+            }
+        }
+
+        fn charge(&mut self, amount : WordCount64) -> bool {
+            if amount > self.words_remaining {
+                self.report("Exceeded message traversal limit. See capnp::ReaderOptions.", None);
+                self.exhausted = true;
+                false
+            } else {
+                self.words_remaining -= amount;
+                true
+            }
+        }
+
+        fn expects_text(&self) -> bool {
+            self.text_paths.iter().any(|pattern| validate::path_matches(*pattern, self.path.as_slice()))
+        }
+    }
+
+    // Like `read_text_pointer()`, but reports problems instead of returning the first one.
+    unsafe fn validate_text(validator : &mut Validator,
+                            segment : *const SegmentReader,
+                            reff : *const WirePointer,
+                            ptr : *const Word) {
+        if (*reff).kind() != WirePointerKind::List {
+            validator.report("Message contains non-list pointer where text was expected.", None);
+            return;
+        }
+        if (*reff).list_ref().element_size() != Byte {
+            validator.report("Message contains list pointer of non-bytes where text was expected.", None);
+            return;
+        }
+
+        let size = (*reff).list_ref().element_count();
+        let word_count = round_bytes_up_to_words(size);
+        if !bounds_check(segment, ptr, ptr.offset(word_count as isize)) {
+            validator.report("Message contains out-of-bounds text pointer.", None);
+            return;
+        }
+        if !validator.charge(word_count as u64) { return }
 
-        data::new_reader(::std::mem::transmute(ptr), size)
+        let str_ptr : *const u8 = ::std::mem::transmute(ptr);
+        if size == 0 || *str_ptr.offset((size - 1) as isize) != 0u8 {
+            validator.report("Message contains text that is not NUL-terminated.", None);
+        }
+    }
+
+    pub unsafe fn validate_pointer(validator : &mut Validator,
+                                   mut segment : *const SegmentReader,
+                                   mut reff : *const WirePointer,
+                                   nesting_limit : i32) {
+        if validator.exhausted || (*reff).is_null() { return }
+
+        if nesting_limit <= 0 {
+            validator.report("Message is too deeply nested.", None);
+            return;
+        }
+
+        let mut ptr = (*reff).target();
+
+        if (*reff).kind() == WirePointerKind::Far {
+            let far_segment_id = (*reff).far_ref().segment_id.get();
+            segment = match (*segment).arena.try_get_segment(far_segment_id) {
+                Ok(s) => s,
+                Err(e) => { validator.report_error(e); return; }
+            };
+
+            let pad_ptr : *const Word =
+                (*segment).get_start_ptr().offset((*reff).far_position_in_segment() as isize);
+            let pad_words : isize = if (*reff).is_double_far() { 2 } else { 1 };
+            if !bounds_check(segment, pad_ptr, pad_ptr.offset(pad_words)) {
+                validator.report("Message contains out-of-bounds far pointer.", None);
+                return;
+            }
+
+            let pad : *const WirePointer = ::std::mem::transmute(pad_ptr);
+            if !(*reff).is_double_far() {
+                if (*pad).kind() == WirePointerKind::Far {
+                    validator.report("Far pointer landing pad is itself a far pointer.", None);
+                    return;
+                }
+                reff = pad;
+                ptr = (*pad).target();
+            } else {
+                if (*pad).kind() != WirePointerKind::Far || (*pad).is_double_far() {
+                    validator.report("First word of double-far landing pad is not a single far pointer.",
+                                     None);
+                    return;
+                }
+                let tag = pad.offset(1);
+                if (*tag).kind() == WirePointerKind::Far {
+                    validator.report("Second word of double-far landing pad is a far pointer.", None);
+                    return;
+                }
+                let content_segment_id = (*pad).far_ref().segment_id.get();
+                segment = match (*segment).arena.try_get_segment(content_segment_id) {
+                    Ok(s) => s,
+                    Err(e) => { validator.report_error(e); return; }
+                };
+                reff = tag;
+                ptr = (*segment).get_start_ptr().offset((*pad).far_position_in_segment() as isize);
+            }
+        }
+
+        if validator.expects_text() {
+            validate_text(validator, segment, reff, ptr);
+            return;
+        }
+
+        match (*reff).kind() {
+            WirePointerKind::Struct => {
+                let word_size = (*reff).struct_ref().word_size();
+                if !bounds_check(segment, ptr, ptr.offset(word_size as isize)) {
+                    validator.report("Message contains out-of-bounds struct pointer.", None);
+                    return;
+                }
+                if !validator.charge(word_size as u64) { return }
+
+                let pointer_section : *const WirePointer =
+                    ::std::mem::transmute(ptr.offset((*reff).struct_ref().data_size.get() as isize));
+                for i in 0..(*reff).struct_ref().ptr_count.get() {
+                    validator.path.push(PathElement::PointerField(i));
+                    validate_pointer(validator, segment, pointer_section.offset(i as isize),
+                                     nesting_limit - 1);
+                    validator.path.pop();
+                }
+            }
+            WirePointerKind::List => {
+                let element_size = (*reff).list_ref().element_size();
+                match element_size {
+                    Void => {
+                        validator.charge((*reff).list_ref().element_count() as u64);
+                    }
+                    Bit | Byte | TwoBytes | FourBytes | EightBytes | Pointer => {
+                        let count = (*reff).list_ref().element_count();
+                        let step = data_bits_per_element(element_size) +
+                            pointers_per_element(element_size) * BITS_PER_POINTER as u32;
+                        let word_count = round_bits_up_to_words(count as u64 * step as u64);
+                        if !bounds_check(segment, ptr, ptr.offset(word_count as isize)) {
+                            validator.report("Message contains out-of-bounds list pointer.", None);
+                            return;
+                        }
+                        if !validator.charge(word_count as u64) { return }
+
+                        if element_size == Pointer {
+                            let pointers : *const WirePointer = ::std::mem::transmute(ptr);
+                            for i in 0..count {
+                                validator.path.push(PathElement::ListElement(i));
+                                validate_pointer(validator, segment, pointers.offset(i as isize),
+                                                 nesting_limit - 1);
+                                validator.path.pop();
+                            }
+                        }
+                    }
+                    InlineComposite => {
+                        let word_count = (*reff).list_ref().inline_composite_word_count();
+                        if !bounds_check(segment, ptr,
+                                         ptr.offset(POINTER_SIZE_IN_WORDS as isize + word_count as isize)) {
+                            validator.report("Message contains out-of-bounds list pointer.", None);
+                            return;
+                        }
+
+                        let tag : *const WirePointer = ::std::mem::transmute(ptr);
+                        if (*tag).kind() != WirePointerKind::Struct {
+                            validator.report("InlineComposite lists of non-STRUCT type are not supported.",
+                                             None);
+                            return;
+                        }
+
+                        let count = (*tag).inline_composite_list_element_count();
+                        let words_per_element = (*tag).struct_ref().word_size();
+                        if words_per_element as u64 * count as u64 > word_count as u64 {
+                            validator.report("InlineComposite list's elements overrun its word count.",
+                                             Some(format!("{} elements of {} words in {} words",
+                                                          count, words_per_element, word_count)));
+                            return;
+                        }
+                        if !validator.charge(word_count as u64 + POINTER_SIZE_IN_WORDS as u64) { return }
+                        if words_per_element == 0 && !validator.charge(count as u64) { return }
+
+                        let data_size = (*tag).struct_ref().data_size.get();
+                        let pointer_count = (*tag).struct_ref().ptr_count.get();
+                        let mut pos : *const Word = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+                        for i in 0..count {
+                            pos = pos.offset(data_size as isize);
+                            validator.path.push(PathElement::ListElement(i));
+                            for j in 0..pointer_count {
+                                validator.path.push(PathElement::PointerField(j));
+                                validate_pointer(validator, segment, ::std::mem::transmute(pos),
+                                                 nesting_limit - 1);
+                                validator.path.pop();
+                                pos = pos.offset(POINTER_SIZE_IN_WORDS as isize);
+                            }
+                            validator.path.pop();
+                        }
+                    }
+                }
+            }
+            WirePointerKind::Far => {
+                unreachable!()
+            }
+            WirePointerKind::Other => {
+                if !(*reff).is_capability() {
+                    validator.report("Unknown pointer type.", None);
+                } else {
+                    let index = (*reff).cap_ref().index.get();
+                    if !(*segment).arena.has_cap(index as usize) {
+                        validator.report("Message contains invalid capability pointer.",
+                                         Some(format!("index: {}", index)));
+                    }
+                }
+            }
+        }
     }
 }
 
 static ZERO : u64 = 0;
 fn zero_pointer() -> *const WirePointer { unsafe {::std::mem::transmute(&ZERO)}}
 
+pub fn validate_root(segment : *const SegmentReader,
+                     traversal_limit_in_words : u64,
+                     nesting_limit : i32,
+                     text_paths : &[&[PathPattern]]) -> Vec<Problem> {
+    let mut validator = wire_helpers::Validator {
+        path : Vec::new(),
+        problems : Vec::new(),
+        words_remaining : traversal_limit_in_words,
+        exhausted : false,
+        text_paths : text_paths,
+    };
+    unsafe {
+        let location = (*segment).get_start_ptr();
+        if !wire_helpers::bounds_check(segment, location,
+                                       location.offset(POINTER_SIZE_IN_WORDS as isize)) {
+            validator.report("Root location out of bounds.", None);
+        } else {
+            wire_helpers::validate_pointer(&mut validator, segment,
+                                           ::std::mem::transmute(location), nesting_limit);
+        }
+    }
+    validator.problems
+}
+
+/// Checks whether the message whose first segment is `segment` is in canonical form: a struct
+/// root, every object laid out in preorder directly after the root pointer, no far pointers, all
+/// structs truncated, and no words left over at the end of the segment. The caller is
+/// responsible for checking that there is only one segment.
+pub fn is_canonical_root(segment : *const SegmentReader, nesting_limit : i32) -> Result<bool> {
+    unsafe {
+        let location = (*segment).get_start_ptr();
+        require!(wire_helpers::bounds_check(segment, location,
+                                            location.offset(POINTER_SIZE_IN_WORDS as isize)),
+                 "Root location out of bounds.");
+        let root : *const WirePointer = ::std::mem::transmute(location);
+        if !(*root).is_null() && (*root).kind() != WirePointerKind::Struct {
+            return Ok(false);
+        }
+
+        let mut read_head = location.offset(POINTER_SIZE_IN_WORDS as isize);
+        let root_is_canonical = try!(wire_helpers::is_canonical(segment, root, &mut read_head,
+                                                                nesting_limit));
+        Ok(root_is_canonical && read_head == location.offset((*segment).size as isize))
+    }
+}
+
+/// What a pointer points at, as reported by `PointerReader::get_pointer_type()`.
+#[derive(Copy, PartialEq, Debug)]
+pub enum PointerType {
+    Null,
+    Struct,
+    List,
+    Capability,
+}
+
+fn hash_u64<H : Hasher>(state : &mut H, value : u64) {
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = (value >> (8 * i)) as u8;
+    }
+    state.write(&bytes);
+}
+
 #[derive(Copy)]
 pub struct PointerReader<'a> {
     marker : ::std::marker::ContravariantLifetime<'a>,
     segment : *const SegmentReader,
     pointer : *const WirePointer,
+    // Non-null if `pointer` is the tag of this orphan.
+    orphan : *const OrphanBuilder<'a>,
     nesting_limit : i32
 }
 
@@ -1838,23 +2611,23 @@ impl <'a> PointerReader<'a> {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : ::std::ptr::null(),
             pointer : ::std::ptr::null(),
+            orphan : ::std::ptr::null(),
             nesting_limit : 0x7fffffff }
     }
 
-    pub fn get_root<'b>(segment : *const SegmentReader, mut location : *const Word,
-                        nesting_limit : i32) -> PointerReader<'b> {
+    pub fn get_root<'b>(segment : *const SegmentReader, location : *const Word,
+                        nesting_limit : i32) -> Result<PointerReader<'b>> {
         unsafe {
             require!(wire_helpers::bounds_check(segment, location,
                                                location.offset(POINTER_SIZE_IN_WORDS as isize)),
-                     *segment,
-                     "Root location out of bounds.",
-                     location = ::std::ptr::null());
+                     "Root location out of bounds.");
 
-            PointerReader {
+            Ok(PointerReader {
                 marker : ::std::marker::ContravariantLifetime::<'a>,
                 segment : segment,
                 pointer : ::std::mem::transmute(location),
-                nesting_limit : nesting_limit }
+                orphan : ::std::ptr::null(),
+                nesting_limit : nesting_limit })
         }
     }
 
@@ -1863,6 +2636,7 @@ impl <'a> PointerReader<'a> {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : ::std::ptr::null(),
             pointer : unsafe { ::std::mem::transmute(location) },
+            orphan : ::std::ptr::null(),
             nesting_limit : 0x7fffffff }
     }
 
@@ -1870,33 +2644,54 @@ impl <'a> PointerReader<'a> {
         self.pointer.is_null() || unsafe { (*self.pointer).is_null() }
     }
 
-    pub fn get_struct(&self, default_value: *const Word) -> StructReader<'a> {
-        let reff : *const WirePointer = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
+    /// Whether this reads an orphan. Such a reader must not outlive the `OrphanBuilder`, which
+    /// holds the tag.
+    pub fn is_orphan(&self) -> bool {
+        !self.orphan.is_null()
+    }
+
+    /// Returns the pointer to read and where it points, before following far pointers. An
+    /// orphan's tag does not say where its object is, so that comes from the orphan.
+    unsafe fn get_pointer_and_target(&self) -> (*const WirePointer, *const Word) {
+        if self.pointer.is_null() {
+            (zero_pointer(), ::std::ptr::null())
+        } else if self.orphan.is_null() {
+            (self.pointer, (*self.pointer).target())
+        } else {
+            (self.pointer, (*self.orphan).location as *const Word)
+        }
+    }
+
+    pub fn get_struct(&self, default_value: *const Word) -> Result<StructReader<'a>> {
         unsafe {
-            wire_helpers::read_struct_pointer(self.segment, reff,
+            let (reff, ref_target) = self.get_pointer_and_target();
+            wire_helpers::read_struct_pointer(self.segment, reff, ref_target,
                                              default_value, self.nesting_limit)
         }
     }
 
-    pub fn get_list(&self, expected_element_size : ElementSize, default_value : *const Word) -> ListReader<'a> {
-        let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
+    pub fn get_list(&self, expected_element_size : ElementSize, default_value : *const Word)
+                    -> Result<ListReader<'a>> {
         unsafe {
+            let (reff, ref_target) = self.get_pointer_and_target();
             wire_helpers::read_list_pointer(self.segment,
-                                           reff,
+                                           reff, ref_target,
                                            default_value,
                                            expected_element_size, self.nesting_limit)
         }
     }
 
-    pub fn get_text(&self, default_value : *const Word, default_size : ByteCount32) -> text::Reader<'a> {
+    pub fn get_text(&self, default_value : *const Word, default_size : ByteCount32) -> Result<text::Reader<'a>> {
         unsafe {
-            wire_helpers::read_text_pointer(self.segment, self.pointer, default_value, default_size)
+            let (reff, ref_target) = self.get_pointer_and_target();
+            wire_helpers::read_text_pointer(self.segment, reff, ref_target, default_value, default_size)
         }
     }
 
-    pub fn get_data(&self, default_value : *const Word, default_size : ByteCount32) -> data::Reader<'a> {
+    pub fn get_data(&self, default_value : *const Word, default_size : ByteCount32) -> Result<data::Reader<'a>> {
         unsafe {
-            wire_helpers::read_data_pointer(self.segment, self.pointer, default_value, default_size)
+            let (reff, ref_target) = self.get_pointer_and_target();
+            wire_helpers::read_data_pointer(self.segment, reff, ref_target, default_value, default_size)
         }
     }
 
@@ -1907,9 +2702,77 @@ impl <'a> PointerReader<'a> {
         }
     }
 
-    pub fn total_size(&self) -> MessageSize {
+    /// Returns the kind of object that this pointer points at, following far pointers.
+    pub fn get_pointer_type(&self) -> Result<PointerType> {
+        if self.is_null() { return Ok(PointerType::Null); }
+        unsafe {
+            let (mut reff, ref_target) = self.get_pointer_and_target();
+            let mut segment = self.segment;
+            try!(wire_helpers::follow_fars(&mut reff, ref_target, &mut segment));
+            match (*reff).kind() {
+                WirePointerKind::Struct => Ok(PointerType::Struct),
+                WirePointerKind::List => Ok(PointerType::List),
+                WirePointerKind::Far => {
+                    Err(::Error::new_decode_error("Malformed double-far pointer.", None))
+                }
+                WirePointerKind::Other => {
+                    require!((*reff).is_capability(), "Unknown pointer type.");
+                    Ok(PointerType::Capability)
+                }
+            }
+        }
+    }
+
+    /// Compares the values that two pointers point at, which may live in different messages
+    /// with different layouts. See `StructReader::equals()` and `ListReader::equals()`.
+    pub fn equals(&self, other : &PointerReader) -> Result<bool> {
+        match (try!(self.get_pointer_type()), try!(other.get_pointer_type())) {
+            (PointerType::Null, PointerType::Null) => Ok(true),
+            (PointerType::Capability, _) | (_, PointerType::Capability) => {
+                Err(::Error::new_decode_error("Cannot compare capabilities.", None))
+            }
+            (PointerType::Struct, PointerType::Struct) => {
+                try!(self.get_struct(::std::ptr::null()))
+                    .equals(&try!(other.get_struct(::std::ptr::null())))
+            }
+            (PointerType::List, PointerType::List) => {
+                try!(self.get_list(Void, ::std::ptr::null()))
+                    .equals(&try!(other.get_list(Void, ::std::ptr::null())))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Computes a hash of the value that this pointer points at. Values that are `equals()`
+    /// hash the same, regardless of which message they live in.
+    pub fn hash(&self) -> Result<u64> {
+        let mut state = SipHasher::new();
+        try!(self.hash_into(&mut state));
+        Ok(state.finish())
+    }
+
+    fn hash_into<H : Hasher>(&self, state : &mut H) -> Result<()> {
+        match try!(self.get_pointer_type()) {
+            PointerType::Null => hash_u64(state, 0),
+            PointerType::Capability => {
+                return Err(::Error::new_decode_error("Cannot hash capabilities.", None));
+            }
+            PointerType::Struct => {
+                hash_u64(state, 1);
+                try!(try!(self.get_struct(::std::ptr::null())).hash_into(state));
+            }
+            PointerType::List => {
+                hash_u64(state, 2);
+                try!(try!(self.get_list(Void, ::std::ptr::null())).hash_into(state));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn total_size(&self) -> Result<MessageSize> {
         unsafe {
-            wire_helpers::total_size(self.segment, self.pointer, self.nesting_limit)
+            let (reff, ref_target) = self.get_pointer_and_target();
+            wire_helpers::total_size(self.segment, reff, ref_target, self.nesting_limit)
         }
     }
 }
@@ -1917,7 +2780,9 @@ impl <'a> PointerReader<'a> {
 pub struct PointerBuilder<'a> {
     marker : ::std::marker::ContravariantLifetime<'a>,
     segment : *mut SegmentBuilder,
-    pointer : *mut WirePointer
+    pointer : *mut WirePointer,
+    // Non-null if `pointer` is the tag of this orphan.
+    orphan : *mut OrphanBuilder<'a>,
 }
 
 impl <'a> PointerBuilder<'a> {
@@ -1926,48 +2791,57 @@ impl <'a> PointerBuilder<'a> {
     pub fn get_root(segment : *mut SegmentBuilder, location : *mut Word) -> PointerBuilder<'a> {
         PointerBuilder {
             marker : ::std::marker::ContravariantLifetime::<'a>,
-            segment : segment, pointer : unsafe { ::std::mem::transmute(location) }}
+            segment : segment, pointer : unsafe { ::std::mem::transmute(location) },
+            orphan : ::std::ptr::null_mut() }
     }
 
     pub fn is_null(&self) -> bool {
         unsafe { (*self.pointer).is_null() }
     }
 
-    pub fn get_struct(&self, size : StructSize, default_value : *const Word) -> StructBuilder<'a> {
+    /// Whether this builds an orphan. Such a builder must not outlive the `OrphanBuilder`, which
+    /// holds the tag.
+    pub fn is_orphan(&self) -> bool {
+        !self.orphan.is_null()
+    }
+
+    pub fn get_struct(&self, size : StructSize, default_value : *const Word) -> Result<StructBuilder<'a>> {
         unsafe {
             wire_helpers::get_writable_struct_pointer(
                 self.pointer,
                 self.segment,
                 size,
-                default_value)
+                default_value,
+                self.orphan)
         }
     }
 
-    pub fn get_list(&self, element_size : ElementSize, default_value : *const Word) -> ListBuilder<'a> {
+    pub fn get_list(&self, element_size : ElementSize, default_value : *const Word) -> Result<ListBuilder<'a>> {
         unsafe {
             wire_helpers::get_writable_list_pointer(
-                self.pointer, self.segment, element_size, default_value)
+                self.pointer, self.segment, element_size, default_value, self.orphan)
         }
     }
 
-    pub fn get_struct_list(&self, element_size : StructSize, default_value : *const Word) -> ListBuilder<'a> {
+    pub fn get_struct_list(&self, element_size : StructSize,
+                           default_value : *const Word) -> Result<ListBuilder<'a>> {
         unsafe {
             wire_helpers::get_writable_struct_list_pointer(
-                self.pointer, self.segment, element_size, default_value)
+                self.pointer, self.segment, element_size, default_value, self.orphan)
         }
     }
 
-    pub fn get_text(&self, default_value : *const Word, default_size : ByteCount32) -> text::Builder<'a> {
+    pub fn get_text(&self, default_value : *const Word, default_size : ByteCount32) -> Result<text::Builder<'a>> {
         unsafe {
             wire_helpers::get_writable_text_pointer(
-                self.pointer, self.segment, default_value, default_size)
+                self.pointer, self.segment, default_value, default_size, self.orphan)
         }
     }
 
-    pub fn get_data(&self, default_value : *const Word, default_size : ByteCount32) -> data::Builder<'a> {
+    pub fn get_data(&self, default_value : *const Word, default_size : ByteCount32) -> Result<data::Builder<'a>> {
         unsafe {
             wire_helpers::get_writable_data_pointer(
-                self.pointer, self.segment, default_value, default_size)
+                self.pointer, self.segment, default_value, default_size, self.orphan)
         }
     }
 
@@ -1978,60 +2852,85 @@ impl <'a> PointerBuilder<'a> {
         }
     }
 
-    pub fn init_struct(&self, size : StructSize) -> StructBuilder<'a> {
+    pub fn init_struct(&self, size : StructSize) -> Result<StructBuilder<'a>> {
         unsafe {
-            wire_helpers::init_struct_pointer(self.pointer, self.segment, size)
+            wire_helpers::init_struct_pointer(self.pointer, self.segment, size, self.orphan)
         }
     }
 
-    pub fn init_list(&self, element_size : ElementSize, element_count : ElementCount32) -> ListBuilder<'a> {
+    pub fn init_list(&self, element_size : ElementSize, element_count : ElementCount32) -> Result<ListBuilder<'a>> {
         unsafe {
             wire_helpers::init_list_pointer(
-                self.pointer, self.segment, element_count, element_size)
+                self.pointer, self.segment, element_count, element_size, self.orphan)
         }
     }
 
     pub fn init_struct_list(&self, element_count : ElementCount32, element_size : StructSize)
-                            -> ListBuilder<'a> {
+                            -> Result<ListBuilder<'a>> {
         unsafe {
             wire_helpers::init_struct_list_pointer(
-                self.pointer, self.segment, element_count, element_size)
+                self.pointer, self.segment, element_count, element_size, self.orphan)
+        }
+    }
+
+    pub fn init_text(&self, size : ByteCount32) -> Result<text::Builder<'a>> {
+        unsafe {
+            Ok(try!(wire_helpers::init_text_pointer(self.pointer, self.segment, size, self.orphan)).value)
         }
     }
 
-    pub fn init_text(&self, size : ByteCount32) -> text::Builder<'a> {
+    pub fn init_data(&self, size : ByteCount32) -> Result<data::Builder<'a>> {
         unsafe {
-            wire_helpers::init_text_pointer(self.pointer, self.segment, size).value
+            Ok(try!(wire_helpers::init_data_pointer(self.pointer, self.segment, size, self.orphan)).value)
         }
     }
 
-    pub fn init_data(&self, size : ByteCount32) -> data::Builder<'a> {
+    pub fn set_struct(&self, value : &StructReader) -> Result<()> {
         unsafe {
-            wire_helpers::init_data_pointer(self.pointer, self.segment, size).value
+            try!(wire_helpers::set_struct_pointer(self.segment, self.pointer, *value, false, self.orphan));
+            Ok(())
         }
     }
 
-    pub fn set_struct(&self, value : &StructReader) {
+    /// Like `set_struct()`, but lays out the copy in canonical form. The copy is only canonical
+    /// if this pointer is the root of an otherwise empty single-segment message.
+    pub fn set_struct_canonical(&self, value : &StructReader) -> Result<()> {
         unsafe {
-            wire_helpers::set_struct_pointer(self.segment, self.pointer, *value);
+            try!(wire_helpers::set_struct_pointer(self.segment, self.pointer, *value, true, self.orphan));
+            Ok(())
         }
     }
 
-    pub fn set_list(&self, value : &ListReader) {
+    /// Deep-copies whatever `other` points at, which may be in another message, replacing
+    /// whatever this pointer pointed at before.
+    pub fn copy_from(&self, other : PointerReader) -> Result<()> {
+        self.clear();
         unsafe {
-            wire_helpers::set_list_pointer(self.segment, self.pointer, *value);
+            let (src, src_target) = other.get_pointer_and_target();
+            try!(wire_helpers::copy_pointer(self.segment, self.pointer, other.segment, src, src_target,
+                                            other.nesting_limit, false, self.orphan));
         }
+        Ok(())
     }
 
-    pub fn set_text(&self, value : &str) {
+    pub fn set_list(&self, value : &ListReader) -> Result<()> {
         unsafe {
-            wire_helpers::set_text_pointer(self.pointer, self.segment, value);
+            try!(wire_helpers::set_list_pointer(self.segment, self.pointer, *value, false, self.orphan));
+            Ok(())
         }
     }
 
-    pub fn set_data(&self, value : &[u8]) {
+    pub fn set_text(&self, value : &str) -> Result<()> {
         unsafe {
-            wire_helpers::set_data_pointer(self.pointer, self.segment, value);
+            try!(wire_helpers::set_text_pointer(self.pointer, self.segment, value, self.orphan));
+            Ok(())
+        }
+    }
+
+    pub fn set_data(&self, value : &[u8]) -> Result<()> {
+        unsafe {
+            try!(wire_helpers::set_data_pointer(self.pointer, self.segment, value, self.orphan));
+            Ok(())
         }
     }
 
@@ -2043,21 +2942,150 @@ impl <'a> PointerBuilder<'a> {
 
     pub fn clear(&self) {
         unsafe {
-            wire_helpers::zero_object(self.segment, self.pointer);
-            ::std::ptr::zero_memory(self.pointer, 1);
+            if self.orphan.is_null() {
+                wire_helpers::zero_object(self.segment, self.pointer);
+                ::std::ptr::zero_memory(self.pointer, 1);
+            } else {
+                wire_helpers::zero_orphan(self.orphan);
+            }
         }
     }
 
     pub fn as_reader(&self) -> PointerReader<'a> {
         unsafe {
-            let segment_reader = &(*self.segment).reader;
+            // An orphan's object may have been reallocated in another segment.
+            let segment = if self.orphan.is_null() { self.segment } else { (*self.orphan).segment };
             PointerReader {
                 marker : ::std::marker::ContravariantLifetime::<'a>,
-                segment : segment_reader,
+                segment : &(*segment).reader,
                 pointer : self.pointer as *const WirePointer,
+                orphan : self.orphan as *const OrphanBuilder<'a>,
                 nesting_limit : 0x7fffffff }
         }
     }
+
+    pub fn get_arena(&self) -> *mut BuilderArena {
+        unsafe { (*self.segment).get_arena() }
+    }
+
+    /// Makes this pointer point at the orphan's object, without copying it. Whatever this pointer
+    /// pointed at before is zeroed.
+    pub fn adopt(&self, mut orphan : OrphanBuilder) -> Result<()> {
+        unsafe {
+            require!((*self.segment).get_arena() == (*orphan.segment).get_arena(),
+                     "Adopted object must live in the same message.");
+
+            self.clear();
+            if !orphan.tag.is_null() {
+                if orphan.tag.is_positional() {
+                    try!(wire_helpers::transfer_pointer_split(self.segment, self.pointer,
+                                                              orphan.segment, &mut orphan.tag,
+                                                              orphan.location));
+                } else {
+                    //# FAR and OTHER pointers are position-independent, so we can just copy.
+                    ::std::ptr::copy_nonoverlapping_memory(self.pointer, &orphan.tag, 1);
+                }
+            }
+
+            //# Take ownership away from the OrphanBuilder.
+            ::std::ptr::zero_memory(&mut orphan.tag, 1);
+            orphan.location = ::std::ptr::null_mut();
+        }
+        Ok(())
+    }
+
+    /// Detaches the object that this pointer points at, leaving this pointer null.
+    pub fn disown(&self) -> Result<OrphanBuilder<'a>> {
+        unsafe {
+            let mut segment = self.segment;
+            let mut location = ::std::ptr::null_mut();
+            if !(*self.pointer).is_null() {
+                if (*self.pointer).kind() == WirePointerKind::Other {
+                    require!((*self.pointer).is_capability(), "Unknown pointer type.");
+                } else {
+                    let mut reff = self.pointer;
+                    location = wire_helpers::follow_builder_fars(&mut reff, (*self.pointer).mut_target(),
+                                                                 &mut segment);
+                }
+            }
+
+            let mut result = OrphanBuilder::new((*self.segment).get_arena());
+            ::std::ptr::copy_nonoverlapping_memory(&mut result.tag, self.pointer as *const WirePointer, 1);
+            result.segment = segment;
+            result.location = location;
+            if !result.tag.is_null() && result.tag.is_positional() {
+                let kind = result.tag.kind();
+                result.tag.set_kind_for_orphan(kind);
+            }
+
+            //# Zero out the pointer that was disowned.
+            ::std::ptr::zero_memory(self.pointer, 1);
+            Ok(result)
+        }
+    }
+}
+
+/// An object in a message that is not reachable from the root. The orphan holds the object's tag
+/// itself, so the orphan takes up no space in the message besides its object. If it is dropped
+/// without being adopted, the object is zeroed.
+pub struct OrphanBuilder<'a> {
+    marker : ::std::marker::ContravariantLifetime<'a>,
+
+    //# Contains an encoded WirePointer representing this object. This may be a FAR pointer.
+    //# Even in that case, `location` points to the eventual object location.
+    tag : WirePointer,
+
+    // The segment containing the object. For a null orphan this is the first segment, which
+    // is only used to find the arena.
+    segment : *mut SegmentBuilder,
+
+    //# Pointer to the object, or null if the pointer is null or a capability.
+    location : *mut Word,
+}
+
+impl <'a> OrphanBuilder<'a> {
+    /// Returns a null orphan. The caller must ensure that `arena` outlives `'b`.
+    pub unsafe fn new<'b>(arena : *mut BuilderArena) -> OrphanBuilder<'b> {
+        OrphanBuilder {
+            marker : ::std::marker::ContravariantLifetime::<'b>,
+            tag : ::std::mem::zeroed(),
+            segment : &mut (*arena).segment0,
+            location : ::std::ptr::null_mut(),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.tag.is_null()
+    }
+
+    /// The returned builder points at `self`, so it must not outlive it.
+    pub fn as_pointer_builder(&mut self) -> PointerBuilder<'a> {
+        let orphan : *mut OrphanBuilder<'a> = self;
+        PointerBuilder {
+            marker : ::std::marker::ContravariantLifetime::<'a>,
+            segment : self.segment,
+            pointer : unsafe { &mut (*orphan).tag },
+            orphan : orphan,
+        }
+    }
+
+    /// The returned reader points at `self`, so it must not outlive it.
+    pub fn as_reader(&self) -> PointerReader<'a> {
+        PointerReader {
+            marker : ::std::marker::ContravariantLifetime::<'a>,
+            segment : unsafe { &(*self.segment).reader },
+            pointer : &self.tag,
+            orphan : self,
+            nesting_limit : 0x7fffffff,
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl <'a> Drop for OrphanBuilder<'a> {
+    fn drop(&mut self) {
+        unsafe { wire_helpers::zero_orphan(self); }
+    }
 }
 
 #[derive(Copy)]
@@ -2068,6 +3096,10 @@ pub struct StructReader<'a> {
     pointers : *const WirePointer,
     data_size : BitCount32,
     pointer_count : WirePointerCount16,
+
+    // Bit offset of the first bit of the data section. Only nonzero when this struct is an
+    // element of a List(Bool) that is being read as a struct list.
+    bit0_offset : BitCount8,
     nesting_limit : i32
 }
 
@@ -2079,7 +3111,7 @@ impl <'a> StructReader<'a>  {
             segment : ::std::ptr::null(),
             data : ::std::ptr::null(),
             pointers : ::std::ptr::null(), data_size : 0, pointer_count : 0,
-            nesting_limit : 0x7fffffff}
+            bit0_offset : 0, nesting_limit : 0x7fffffff}
     }
 
     pub fn get_data_section_size(&self) -> BitCount32 { self.data_size }
@@ -2105,8 +3137,11 @@ impl <'a> StructReader<'a>  {
 
     #[inline]
     pub fn get_bool_field(&self, offset : ElementCount) -> bool {
-        let boffset : BitCount32 = offset as BitCount32;
+        let mut boffset : BitCount32 = offset as BitCount32;
         if boffset < self.data_size {
+            if offset == 0 {
+                boffset = self.bit0_offset as BitCount32;
+            }
             unsafe {
                 let b : *const u8 = self.data.offset((boffset as usize / BITS_PER_BYTE) as isize);
                 ((*b) & (1u8 << (boffset as u32 % BITS_PER_BYTE as u32) as usize)) != 0
@@ -2137,6 +3172,7 @@ impl <'a> StructReader<'a>  {
                 marker : ::std::marker::ContravariantLifetime::<'a>,
                 segment : self.segment,
                 pointer : unsafe { self.pointers.offset(ptr_index as isize) },
+                orphan : ::std::ptr::null(),
                 nesting_limit : self.nesting_limit
             }
         } else {
@@ -2144,7 +3180,58 @@ impl <'a> StructReader<'a>  {
         }
     }
 
-    pub fn total_size(&self) -> MessageSize {
+    /// Returns the byte at `offset` in the data section, or zero if the data section is too short
+    /// to contain it. A single-bit data section, as found in elements of a bool list, reads as
+    /// one byte holding that bit.
+    fn get_data_byte(&self, offset : ElementCount) -> u8 {
+        if self.data_size == 1 {
+            if offset == 0 { self.get_bool_field(0) as u8 } else { 0 }
+        } else {
+            self.get_data_field::<u8>(offset)
+        }
+    }
+
+    fn get_data_byte_count(&self) -> ElementCount {
+        if self.data_size == 1 { 1 } else { self.data_size as usize / BITS_PER_BYTE }
+    }
+
+    /// Compares two structs field by field. Data past the end of a struct's data section reads as
+    /// zero and pointers past the end of its pointer section read as null, just as they do for
+    /// field accessors, so structs written with different versions of a schema compare equal
+    /// when they hold the same values.
+    pub fn equals(&self, other : &StructReader) -> Result<bool> {
+        for i in 0..::std::cmp::max(self.get_data_byte_count(), other.get_data_byte_count()) {
+            if self.get_data_byte(i) != other.get_data_byte(i) { return Ok(false); }
+        }
+        let pointer_count = ::std::cmp::max(self.pointer_count, other.pointer_count) as WirePointerCount;
+        for i in 0..pointer_count {
+            if !try!(self.get_pointer_field(i).equals(&other.get_pointer_field(i))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn hash_into<H : Hasher>(&self, state : &mut H) -> Result<()> {
+        // Trailing zero data and trailing null pointers are indistinguishable from absent ones,
+        // so they are left out.
+        let mut byte_count = self.get_data_byte_count();
+        while byte_count > 0 && self.get_data_byte(byte_count - 1) == 0 { byte_count -= 1; }
+        let mut pointer_count = self.pointer_count as WirePointerCount;
+        while pointer_count > 0 && self.get_pointer_field(pointer_count - 1).is_null() { pointer_count -= 1; }
+
+        hash_u64(state, byte_count as u64);
+        for i in 0..byte_count {
+            state.write(&[self.get_data_byte(i)]);
+        }
+        hash_u64(state, pointer_count as u64);
+        for i in 0..pointer_count {
+            try!(self.get_pointer_field(i).hash_into(state));
+        }
+        Ok(())
+    }
+
+    pub fn total_size(&self) -> Result<MessageSize> {
         let mut result = MessageSize {
             word_count : wire_helpers::round_bits_up_to_words(self.data_size as u64) as u64 +
                 self.pointer_count as u64 * WORDS_PER_POINTER as u64,
@@ -2152,14 +3239,19 @@ impl <'a> StructReader<'a>  {
 
         for i in 0.. self.pointer_count as isize {
             unsafe {
-                result.plus_eq(wire_helpers::total_size(self.segment, self.pointers.offset(i),
-                                                       self.nesting_limit));
+                let pointer = self.pointers.offset(i);
+                result.plus_eq(try!(wire_helpers::total_size(self.segment, pointer, (*pointer).target(),
+                                                            self.nesting_limit)));
             }
         }
 
-        // TODO when we have read limiting: segment->unread()
+        if !self.segment.is_null() {
+            //# This traversal should not count against the read limit, because it's highly likely
+            //# that the caller is going to traverse the object again, e.g. to copy it.
+            unsafe { (*self.segment).arena.unread(result.word_count) };
+        }
 
-        result
+        Ok(result)
     }
 }
 
@@ -2184,6 +3276,7 @@ impl <'a> StructBuilder<'a> {
                 pointers : ::std::mem::transmute(self.pointers),
                 data_size : self.data_size,
                 pointer_count : self.pointer_count,
+                bit0_offset : 0,
                 nesting_limit : 0x7fffffff
             }
         }
@@ -2259,7 +3352,8 @@ impl <'a> StructBuilder<'a> {
         PointerBuilder {
             marker : ::std::marker::ContravariantLifetime::<'a>,
             segment : self.segment,
-            pointer : unsafe { self.pointers.offset(ptr_index as isize) }
+            pointer : unsafe { self.pointers.offset(ptr_index as isize) },
+            orphan : ::std::ptr::null_mut(),
         }
     }
 
@@ -2274,6 +3368,9 @@ pub struct ListReader<'a> {
     step : BitCount32,
     struct_data_size : BitCount32,
     struct_pointer_count : WirePointerCount16,
+    // The element size that the list is encoded with, which for a struct list whose elements
+    // happen to be small is not implied by `step`.
+    element_size : ElementSize,
     nesting_limit : i32
 }
 
@@ -2284,17 +3381,13 @@ impl <'a> ListReader<'a> {
             marker : ::std::marker::ContravariantLifetime::<'b>,
             segment : ::std::ptr::null(),
             ptr : ::std::ptr::null(), element_count : 0, step: 0, struct_data_size : 0,
-            struct_pointer_count : 0, nesting_limit : 0x7fffffff}
+            struct_pointer_count : 0, element_size : Void, nesting_limit : 0x7fffffff}
     }
 
     #[inline]
     pub fn len(&self) -> ElementCount32 { self.element_count }
 
     pub fn get_struct_element(&self, index : ElementCount32) -> StructReader<'a> {
-        require!(self.nesting_limit > 0, unsafe {*self.segment},
-                 "Message is too deeply-nested or contains cycles",
-                 return StructReader::new_default());
-
         let index_bit : BitCount64 = index as ElementCount64 * (self.step as BitCount64);
 
         let struct_data : *const u8 = unsafe {
@@ -2318,10 +3411,42 @@ impl <'a> ListReader<'a> {
             pointers : struct_pointers,
             data_size : self.struct_data_size as BitCount32,
             pointer_count : self.struct_pointer_count,
+            bit0_offset : (index_bit % BITS_PER_BYTE as u64) as BitCount8,
             nesting_limit : self.nesting_limit - 1
         }
     }
 
+    /// Lists of elements smaller than a word only hold data of that size, so they must agree on
+    /// it. Larger elements all read as structs, which lets a struct list compare equal to a list
+    /// of the same values written with an older or newer version of its element type.
+    fn get_element_class(&self) -> BitCount32 {
+        ::std::cmp::min(self.step, BITS_PER_WORD as BitCount32)
+    }
+
+    /// Compares two lists element by element, comparing the elements as structs. See
+    /// `StructReader::equals()`.
+    pub fn equals(&self, other : &ListReader) -> Result<bool> {
+        if self.element_count != other.element_count ||
+            self.get_element_class() != other.get_element_class() {
+            return Ok(false);
+        }
+        for i in 0..self.element_count {
+            if !try!(self.get_struct_element(i).equals(&other.get_struct_element(i))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn hash_into<H : Hasher>(&self, state : &mut H) -> Result<()> {
+        hash_u64(state, self.element_count as u64);
+        hash_u64(state, self.get_element_class() as u64);
+        for i in 0..self.element_count {
+            try!(self.get_struct_element(i).hash_into(state));
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn get_pointer_element(&self, index : ElementCount32) -> PointerReader<'a> {
         PointerReader {
@@ -2330,6 +3455,7 @@ impl <'a> ListReader<'a> {
             pointer : unsafe {
                 ::std::mem::transmute(self.ptr.offset((index * self.step / BITS_PER_BYTE as u32) as isize))
             },
+            orphan : ::std::ptr::null(),
             nesting_limit : self.nesting_limit
         }
     }
@@ -2384,7 +3510,8 @@ impl <'a> ListBuilder<'a> {
             segment : self.segment,
             pointer : unsafe {
                 ::std::mem::transmute(self.ptr.offset((index * self.step / BITS_PER_BYTE as u32) as isize))
-            }
+            },
+            orphan : ::std::ptr::null_mut(),
         }
     }
 }
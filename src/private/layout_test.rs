@@ -19,6 +19,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use message::MallocMessageBuilder;
+use private::layout::PointerBuilder;
+
+/// Returns the root pointer of `message`, for tests that work below the level of `any_pointer`.
+fn get_root<'a>(message : &'a mut MallocMessageBuilder) -> PointerBuilder<'a> {
+    use message::MessageBuilder;
+    use private::arena::SegmentBuilder;
+
+    message.get_root_internal().unwrap();
+    let segment = &mut message.mut_arena().segment0 as *mut SegmentBuilder;
+    PointerBuilder::get_root(segment, unsafe { (*segment).get_ptr_unchecked(0) })
+}
 
 #[test]
 fn simple_raw_data_struct() {
@@ -29,7 +41,7 @@ fn simple_raw_data_struct() {
     };
 
     let reader = unsafe { ::private::layout::PointerReader::get_root_unchecked(
-        ::std::mem::transmute(data.data.as_ptr())).get_struct(::std::ptr::null()) };
+        ::std::mem::transmute(data.data.as_ptr())).get_struct(::std::ptr::null()).unwrap() };
 
     assert_eq!(0xefcdab8967452301u64, reader.get_data_field::<u64>(0));
     assert_eq!(0, reader.get_data_field::<u64>(1));
@@ -61,3 +73,929 @@ fn simple_raw_data_struct() {
     assert_eq!(reader.get_bool_field(63), true);
     assert_eq!(reader.get_bool_field(64), false);
 }
+
+#[test]
+fn out_of_bounds_struct_pointer() {
+    use message::{MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use private::layout::PointerReader;
+    use Word;
+
+    // A root struct pointer with a one-word data section, but no room for it in the segment.
+    let data : ::private::AlignedData<[u8; 8]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+    };
+
+    let segments = [Word::bytes_to_words(&data.data)];
+    let message = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    let segment = &message.arena().segment0;
+    let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+
+    assert!(root.get_struct(::std::ptr::null()).is_err());
+    assert!(root.total_size().is_err());
+}
+
+#[test]
+fn void_list_amplification() {
+    use message::{MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use primitive_list;
+    use Word;
+
+    // A root pointer to a List(Void) with 2^28 elements. It occupies no space in the segment,
+    // but traversing it should still be charged against the read limit.
+    let data : ::private::AlignedData<[u8; 8]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80]
+    };
+    let segments = [Word::bytes_to_words(&data.data)];
+
+    let message = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    assert!(message.get_root::<primitive_list::Reader<()>>().is_err());
+
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(1 << 30);
+    let message = SegmentArrayMessageReader::new(&segments, options);
+    assert_eq!(message.get_root::<primitive_list::Reader<()>>().unwrap().len(), 1 << 28);
+}
+
+#[test]
+fn validate_reports_every_problem() {
+    use message::{MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use validate::PathElement;
+    use Word;
+
+    // A root struct with two pointer fields: the first points out of bounds and the second
+    // is an "other" pointer that is not a capability.
+    let data : ::private::AlignedData<[u8; 24]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+                0x14, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+                0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    };
+    let segments = [Word::bytes_to_words(&data.data)];
+
+    let message = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    let problems = message.validate();
+    assert_eq!(problems.len(), 2);
+    assert_eq!(problems[0].path, vec![PathElement::PointerField(0)]);
+    assert_eq!(problems[1].path, vec![PathElement::PointerField(1)]);
+}
+
+#[test]
+fn validate_declared_text() {
+    use message::{MallocMessageBuilder, MessageBuilder, MessageReader, ReaderOptions,
+                  SegmentArrayMessageReader};
+    use private::layout::{Pointer, StructSize};
+    use validate::{PathElement, PathPattern};
+
+    let mut message = MallocMessageBuilder::new_default();
+    {
+        let root = get_root(&mut message).init_struct(StructSize { data : 0, pointers : 3 }).unwrap();
+        root.get_pointer_field(0).set_text("text").unwrap();
+        root.get_pointer_field(1).set_data(b"data").unwrap();
+        let list = root.get_pointer_field(2).init_list(Pointer, 2).unwrap();
+        list.get_pointer_element(0).set_text("text").unwrap();
+        list.get_pointer_element(1).set_data(b"data").unwrap();
+    }
+
+    message.get_segments_for_output(|segments| {
+        let reader = SegmentArrayMessageReader::new(segments, ReaderOptions::new());
+        assert!(reader.validate().is_empty());
+
+        // Data without a NUL terminator is only a problem where text is expected.
+        let field_0 : &[PathPattern] = &[PathPattern::PointerField(0)];
+        let field_1 : &[PathPattern] = &[PathPattern::PointerField(1)];
+        let field_2_elements : &[PathPattern] = &[PathPattern::PointerField(2), PathPattern::AnyListElement];
+        let problems = reader.validate_with_text(&[field_0, field_1, field_2_elements]);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].path, vec![PathElement::PointerField(1)]);
+        assert_eq!(problems[1].path, vec![PathElement::PointerField(2), PathElement::ListElement(1)]);
+
+        // The list itself is not text.
+        let field_2 : &[PathPattern] = &[PathPattern::PointerField(2)];
+        assert_eq!(reader.validate_with_text(&[field_2]).len(), 1);
+    });
+}
+
+#[test]
+fn struct_default_value() {
+    use message::MallocMessageBuilder;
+    use private::layout::{PointerReader, StructSize};
+
+    // A struct with a single data word.
+    let default : ::private::AlignedData<[u8; 16]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]
+    };
+    let default_value : *const ::Word = unsafe { ::std::mem::transmute(default.data.as_ptr()) };
+
+    let reader = PointerReader::new_default().get_struct(default_value).unwrap();
+    assert_eq!(0xefcdab8967452301u64, reader.get_data_field::<u64>(0));
+
+    let mut message = MallocMessageBuilder::new_default();
+    let root = get_root(&mut message);
+    let size = StructSize { data : 1, pointers : 1 };
+
+    let builder = root.get_struct(size, default_value).unwrap();
+    assert_eq!(0xefcdab8967452301u64, builder.get_data_field::<u64>(0));
+    builder.set_data_field::<u64>(0, 42);
+
+    // The default was copied into the message, so later reads see the modification.
+    let builder = root.get_struct(size, default_value).unwrap();
+    assert_eq!(42, builder.get_data_field::<u64>(0));
+}
+
+#[test]
+fn list_default_value() {
+    use message::MallocMessageBuilder;
+    use private::layout::{Pointer, PointerReader};
+    use std::str::Str;
+
+    // The List(Text) value ["a", "b"].
+    let default : ::private::AlignedData<[u8; 40]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x01, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+                0x05, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00,
+                0x05, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00,
+                0x61, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x62, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    };
+    let default_value : *const ::Word = unsafe { ::std::mem::transmute(default.data.as_ptr()) };
+
+    let reader = PointerReader::new_default().get_list(Pointer, default_value).unwrap();
+    assert_eq!(reader.len(), 2);
+    assert_eq!(reader.get_pointer_element(0).get_text(::std::ptr::null(), 0).unwrap(), "a");
+    assert_eq!(reader.get_pointer_element(1).get_text(::std::ptr::null(), 0).unwrap(), "b");
+
+    let mut message = MallocMessageBuilder::new_default();
+    let root = get_root(&mut message);
+
+    let builder = root.get_list(Pointer, default_value).unwrap();
+    assert_eq!(builder.len(), 2);
+    assert_eq!(builder.get_pointer_element(1).get_text(::std::ptr::null(), 0).unwrap().as_slice(), "b");
+    builder.get_pointer_element(1).set_text("c").unwrap();
+
+    let builder = root.get_list(Pointer, default_value).unwrap();
+    assert_eq!(builder.get_pointer_element(1).get_text(::std::ptr::null(), 0).unwrap().as_slice(), "c");
+}
+
+#[test]
+fn upgrade_primitive_list_to_struct_list() {
+    use message::MallocMessageBuilder;
+    use private::layout::{FourBytes, PrimitiveElement, StructSize};
+
+    let mut message = MallocMessageBuilder::new_default();
+    let root = get_root(&mut message);
+
+    // Written by an older schema as List(UInt32).
+    let list = root.init_list(FourBytes, 3).unwrap();
+    for i in 0..3 {
+        PrimitiveElement::set(&list, i, 100u32 + i);
+    }
+
+    // Opened by a newer schema as a list of structs whose first field is the old value.
+    let size = StructSize { data : 1, pointers : 1 };
+    let structs = root.get_struct_list(size, ::std::ptr::null()).unwrap();
+    assert_eq!(structs.len(), 3);
+    for i in 0..3 {
+        let element = structs.get_struct_element(i);
+        assert_eq!(element.get_data_field::<u32>(0), 100 + i);
+        assert_eq!(element.get_data_field::<u32>(1), 0);
+        assert!(element.get_pointer_field(0).is_null());
+    }
+
+    // Expanding an existing struct list keeps the old fields.
+    let bigger = StructSize { data : 2, pointers : 2 };
+    let structs = root.get_struct_list(bigger, ::std::ptr::null()).unwrap();
+    assert_eq!(structs.len(), 3);
+    for i in 0..3 {
+        let element = structs.get_struct_element(i);
+        assert_eq!(element.get_data_field::<u32>(0), 100 + i);
+        assert_eq!(element.get_data_field::<u64>(1), 0);
+    }
+}
+
+#[test]
+fn read_primitive_lists_as_struct_lists() {
+    use message::{MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use private::layout::{InlineComposite, PointerReader, TwoBytes};
+    use Word;
+
+    // The List(Bool) value [true, false, true].
+    let bools : ::private::AlignedData<[u8; 16]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x01, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00, 0x00,
+                0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    };
+    let segments = [Word::bytes_to_words(&bools.data)];
+    let message = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    let segment = &message.arena().segment0;
+    let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+
+    let list = root.get_list(InlineComposite, ::std::ptr::null()).unwrap();
+    assert_eq!(list.len(), 3);
+    assert!(list.get_struct_element(0).get_bool_field(0));
+    assert!(!list.get_struct_element(1).get_bool_field(0));
+    assert!(list.get_struct_element(2).get_bool_field(0));
+    assert_eq!(list.get_struct_element(2).get_data_field::<u8>(0), 0);
+    assert!(list.get_struct_element(2).get_pointer_field(0).is_null());
+
+    // The List(UInt16) value [0x1234, 0x5678].
+    let shorts : ::private::AlignedData<[u8; 16]> = ::private::AlignedData {
+        _dummy : 0,
+        data : [0x01, 0x00, 0x00, 0x00, 0x13, 0x00, 0x00, 0x00,
+                0x34, 0x12, 0x78, 0x56, 0x00, 0x00, 0x00, 0x00]
+    };
+    let segments = [Word::bytes_to_words(&shorts.data)];
+    let message = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    let segment = &message.arena().segment0;
+    let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+
+    let list = root.get_list(InlineComposite, ::std::ptr::null()).unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.get_struct_element(0).get_data_field::<u16>(0), 0x1234);
+    assert_eq!(list.get_struct_element(1).get_data_field::<u16>(0), 0x5678);
+    assert_eq!(list.get_struct_element(1).get_data_field::<u32>(0), 0);
+
+    let list = root.get_list(TwoBytes, ::std::ptr::null()).unwrap();
+    assert_eq!(list.len(), 2);
+}
+
+#[test]
+fn double_far_when_upgrading_struct() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder,
+                  MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use private::layout::{PointerReader, StructSize};
+    use std::str::Str;
+
+    // Fill the first segment exactly: the root pointer, a struct with one pointer field, and the
+    // text it points to.
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(3).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut message);
+        root.init_struct(StructSize { data : 0, pointers : 1 }).unwrap().get_pointer_field(0).set_text("hi").unwrap();
+
+        // Growing the struct moves it to a new segment. The text stays behind in the first
+        // segment, which has no room for a landing pad, so the moved pointer field must become
+        // a double-far.
+        let upgraded = root.get_struct(StructSize { data : 1, pointers : 1 }, ::std::ptr::null()).unwrap();
+        upgraded.set_data_field::<u64>(0, 7);
+        assert_eq!(upgraded.get_pointer_field(0).get_text(::std::ptr::null(), 0).unwrap().as_slice(), "hi");
+    }
+    assert_eq!(message.arena().more_segments.len(), 2);
+
+    message.get_segments_for_output(|segments| {
+        let reader = SegmentArrayMessageReader::new(segments, ReaderOptions::new());
+        let segment = &reader.arena().segment0;
+        let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+        let root = root.get_struct(::std::ptr::null()).unwrap();
+        assert_eq!(root.get_data_field::<u64>(0), 7);
+        assert_eq!(root.get_pointer_field(0).get_text(::std::ptr::null(), 0).unwrap(), "hi");
+        assert!(reader.validate().is_empty());
+    });
+}
+
+#[test]
+fn double_far_when_upgrading_struct_list() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder,
+                  MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use private::layout::{InlineComposite, PointerReader, StructSize};
+    use std::str::Str;
+
+    // The root pointer, a two-element struct list with one pointer field each, and two texts.
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(6).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut message);
+        let list = root.init_struct_list(2, StructSize { data : 0, pointers : 1 }).unwrap();
+        list.get_struct_element(0).get_pointer_field(0).set_text("a").unwrap();
+        list.get_struct_element(1).get_pointer_field(0).set_text("b").unwrap();
+
+        let bigger = StructSize { data : 1, pointers : 1 };
+        let list = root.get_struct_list(bigger, ::std::ptr::null()).unwrap();
+        assert_eq!(list.get_struct_element(0).get_pointer_field(0)
+                   .get_text(::std::ptr::null(), 0).unwrap().as_slice(), "a");
+        assert_eq!(list.get_struct_element(1).get_pointer_field(0)
+                   .get_text(::std::ptr::null(), 0).unwrap().as_slice(), "b");
+    }
+
+    message.get_segments_for_output(|segments| {
+        let reader = SegmentArrayMessageReader::new(segments, ReaderOptions::new());
+        let segment = &reader.arena().segment0;
+        let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+        let list = root.get_list(InlineComposite, ::std::ptr::null()).unwrap();
+        assert_eq!(list.get_struct_element(0).get_pointer_field(0)
+                   .get_text(::std::ptr::null(), 0).unwrap(), "a");
+        assert_eq!(list.get_struct_element(1).get_pointer_field(0)
+                   .get_text(::std::ptr::null(), 0).unwrap(), "b");
+        assert!(reader.validate().is_empty());
+    });
+}
+
+#[test]
+fn failed_upgrade_keeps_old_value() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder};
+    use private::layout::{FourBytes, PrimitiveElement, StructSize};
+
+    // Every message below fills most of its only segment, so no upgrade has room to succeed.
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(3).allocation_strategy(AllocationStrategy::FixedSize).max_segments(1);
+
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut message);
+        let small = StructSize { data : 1, pointers : 0 };
+        root.init_struct(small).unwrap().set_data_field::<u64>(0, 7);
+        assert!(root.get_struct(StructSize { data : 1, pointers : 1 }, ::std::ptr::null()).is_err());
+        assert_eq!(root.get_struct(small, ::std::ptr::null()).unwrap().get_data_field::<u64>(0), 7);
+    }
+
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut message);
+        let list = root.init_list(FourBytes, 2).unwrap();
+        PrimitiveElement::set(&list, 0, 100u32);
+        PrimitiveElement::set(&list, 1, 101u32);
+        assert!(root.get_struct_list(StructSize { data : 1, pointers : 0 }, ::std::ptr::null()).is_err());
+        let list = root.get_list(FourBytes, ::std::ptr::null()).unwrap();
+        let value : u32 = PrimitiveElement::get_from_builder(&list, 1);
+        assert_eq!(value, 101);
+    }
+
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut message);
+        let small = StructSize { data : 1, pointers : 0 };
+        root.init_struct_list(1, small).unwrap().get_struct_element(0).set_data_field::<u64>(0, 7);
+        assert!(root.get_struct_list(StructSize { data : 2, pointers : 0 }, ::std::ptr::null()).is_err());
+        let list = root.get_struct_list(small, ::std::ptr::null()).unwrap();
+        assert_eq!(list.get_struct_element(0).get_data_field::<u64>(0), 7);
+    }
+}
+
+#[test]
+fn overwrite_unknown_pointer() {
+    use message::{MallocMessageBuilder, MessageBuilder};
+    use private::layout::StructSize;
+
+    let mut message = MallocMessageBuilder::new_default();
+    message.get_root_internal().unwrap();
+
+    // An "other" pointer that is not a capability.
+    let location = message.mut_arena().segment0.get_ptr_unchecked(0);
+    unsafe { *::std::mem::transmute::<*mut ::Word, *mut u64>(location) = 7; }
+
+    let root = get_root(&mut message);
+    assert!(root.as_reader().get_struct(::std::ptr::null()).is_err());
+    assert!(root.get_struct(StructSize { data : 1, pointers : 0 }, ::std::ptr::null()).is_err());
+
+    let builder = root.init_struct(StructSize { data : 1, pointers : 0 }).unwrap();
+    builder.set_data_field::<u64>(0, 11);
+    assert_eq!(root.as_reader().get_struct(::std::ptr::null()).unwrap().get_data_field::<u64>(0), 11);
+}
+
+#[test]
+fn broken_capability() {
+    use private::layout::PointerReader;
+
+    // Reading a capability from a null pointer yields a client whose calls all fail.
+    let client = PointerReader::new_default().get_capability();
+    let request = client.copy().new_call(0, 0, None);
+    assert!(request.hook.send().answer_result.is_err());
+
+    // So does a capability pointer in an unchecked message, which has no cap table.
+    let data : ::private::AlignedData<[u8; 8]> = ::private::AlignedData {
+        _dummy: 0,
+        data : [0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    };
+    let client = unsafe {
+        PointerReader::get_root_unchecked(::std::mem::transmute(data.data.as_ptr())).get_capability()
+    };
+    let request = client.copy().new_call(0, 0, None);
+    assert!(request.hook.send().answer_result.is_err());
+}
+
+#[test]
+fn list_size_limits() {
+    use message::{MallocMessageBuilder, MessageBuilder};
+    use private::layout::{Byte, StructSize};
+
+    let mut message = MallocMessageBuilder::new_default();
+    {
+        let root = get_root(&mut message);
+
+        assert!(root.init_list(Byte, 1 << 29).is_err());
+        assert!(root.init_text((1 << 29) - 1).is_err());
+        assert!(root.init_data(1 << 29).is_err());
+
+        // Element count is in range, but the total word count would overflow a u32.
+        assert!(root.init_struct_list(1 << 28, StructSize { data : 32, pointers : 0 }).is_err());
+
+        // Nothing was allocated by the failed attempts.
+        assert!(root.is_null());
+    }
+    assert_eq!(message.arena().segment0.current_size(), 1);
+}
+
+#[test]
+fn adopt_and_disown() {
+    use message::{MallocMessageBuilder, MessageBuilder};
+    use primitive_list;
+    use text;
+
+    let mut message = MallocMessageBuilder::new_default();
+    let orphanage = message.get_root_internal().unwrap().get_orphanage();
+    let root_words = message.arena().words_used();
+
+    // The orphan's tag is not stored in the message; only the list's two words are.
+    let mut orphan = orphanage.new_orphan_sized::<primitive_list::Builder<u32>>(3).unwrap();
+    {
+        let mut list = orphan.get().unwrap();
+        for i in 0..3 {
+            list.set(i, 10 * i);
+        }
+    }
+    assert!(!orphan.is_null());
+    assert_eq!(message.arena().words_used(), root_words + 2);
+
+    // An orphan that is dropped without being adopted leaves nothing behind but its zeroed
+    // object.
+    {
+        let garbage = orphanage.new_orphan_copy::<text::Builder, text::Reader>("garbage").unwrap();
+        assert!(!garbage.is_null());
+        assert_eq!(message.arena().words_used(), root_words + 3);
+    }
+    assert_eq!(message.arena().words_used(), root_words + 3);
+    message.get_segments_for_output(|segments| {
+        assert!(::Word::words_to_bytes(&segments[0][3..4]).iter().all(|&b| b == 0));
+    });
+
+    // Adopting, disowning and adopting again only moves the tag.
+    message.get_root_internal().unwrap().adopt(orphan).unwrap();
+    {
+        let list = message.get_root::<primitive_list::Builder<u32>>().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.get(2), 20);
+    }
+
+    let orphan = message.get_root_internal().unwrap().disown::<primitive_list::Builder<u32>>().unwrap();
+    assert!(message.get_root_internal().unwrap().as_reader().is_null());
+    assert_eq!(orphan.get_reader::<primitive_list::Reader<u32>>().unwrap().get(1), 10);
+
+    message.get_root_internal().unwrap().adopt(orphan).unwrap();
+    assert_eq!(message.get_root::<primitive_list::Builder<u32>>().unwrap().get(1), 10);
+    assert_eq!(message.arena().words_used(), root_words + 3);
+}
+
+#[test]
+fn custom_allocator() {
+    use message::{Allocator, AllocationStrategy, BuilderOptions, HeapAllocator, MallocMessageBuilder,
+                  MessageBuilder, ScratchSpaceMallocMessageBuilder};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use text;
+
+    struct CountingAllocator {
+        allocator : HeapAllocator,
+        live_segments : Rc<Cell<u32>>,
+    }
+
+    impl Allocator for CountingAllocator {
+        fn allocate_segment(&mut self, minimum_size : u32) -> ::Result<(*mut ::Word, u32)> {
+            self.live_segments.set(self.live_segments.get() + 1);
+            self.allocator.allocate_segment(minimum_size)
+        }
+        fn deallocate_segment(&mut self, ptr : *mut ::Word, word_size : u32, words_used : u32) {
+            self.live_segments.set(self.live_segments.get() - 1);
+            self.allocator.deallocate_segment(ptr, word_size, words_used)
+        }
+    }
+
+    let live_segments = Rc::new(Cell::new(0));
+    {
+        let mut options = BuilderOptions::new();
+        options.first_segment_words(2).allocation_strategy(AllocationStrategy::FixedSize);
+        let mut message = MallocMessageBuilder::new_with_allocator(
+            CountingAllocator { allocator : HeapAllocator::new(options),
+                                live_segments : live_segments.clone() }).unwrap();
+        message.set_root::<text::Builder, text::Reader>("more than two words of text").unwrap();
+        assert_eq!(live_segments.get(), 1 + message.arena().more_segments.len() as u32);
+        assert!(live_segments.get() > 1);
+    }
+    assert_eq!(live_segments.get(), 0);
+
+    // The scratch space is handed back zeroed, ready for the next message.
+    let mut scratch_space = ::Word::allocate_zeroed_vec(16);
+    {
+        let mut message = ScratchSpaceMallocMessageBuilder::new_default(scratch_space.as_mut_slice());
+        message.set_root::<text::Builder, text::Reader>("hello").unwrap();
+    }
+    assert!(::Word::words_to_bytes(scratch_space.as_slice()).iter().all(|&b| b == 0));
+}
+
+#[test]
+fn reuse_builder_segments() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilderPool, MessageBuilder};
+    use std::str::Str;
+    use text;
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(2).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut pool = MallocMessageBuilderPool::new(options, 1);
+
+    let mut message = pool.get().unwrap();
+    message.set_root::<text::Builder, text::Reader>("more than two words of text").unwrap();
+    let num_segments = message.arena().more_segments.len();
+    assert!(num_segments > 0);
+    pool.put(message);
+
+    // The second message fits in the segments left over from the first one.
+    let mut message = pool.get().unwrap();
+    assert_eq!(message.arena().segment0.current_size(), 0);
+    message.set_root::<text::Builder, text::Reader>("two words of text").unwrap();
+    assert_eq!(message.arena().more_segments.len(), num_segments);
+    assert_eq!(message.get_root::<text::Builder>().unwrap().as_slice(), "two words of text");
+    pool.put(message);
+
+    // Segments that the third message doesn't need are left out of its output.
+    let mut message = pool.get().unwrap();
+    message.set_root::<text::Builder, text::Reader>("hi").unwrap();
+    assert_eq!(message.arena().more_segments.len(), num_segments);
+    assert_eq!(message.get_segments_for_output(|segments| segments.len()), 1);
+}
+
+#[test]
+fn builder_memory_limits() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder};
+    use primitive_list;
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(4).allocation_strategy(AllocationStrategy::FixedSize).max_total_words(20);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+
+    // The first segment counts against the limit, leaving 16 words.
+    assert!(message.init_root::<primitive_list::Builder<u64>>().is_ok());
+    assert!(message.get_root_internal().unwrap().init_as_sized::<primitive_list::Builder<u64>>(17).is_err());
+    assert!(message.get_root_internal().unwrap().init_as_sized::<primitive_list::Builder<u64>>(10).is_ok());
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(2).allocation_strategy(AllocationStrategy::FixedSize).max_segments(2);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    assert!(message.get_root_internal().unwrap().init_as_sized::<primitive_list::Builder<u64>>(2).is_ok());
+    match message.get_root_internal().unwrap().init_as_sized::<primitive_list::Builder<u64>>(3) {
+        Err(::Error::ResourceLimit { .. }) => {}
+        _ => panic!("expected a resource limit error"),
+    }
+
+    // Not even the first segment can be allocated.
+    let mut options = BuilderOptions::new();
+    options.max_segments(0);
+    assert!(MallocMessageBuilder::new(options).is_err());
+
+    // The first segment is empty, so there is no room for the root pointer.
+    let mut options = BuilderOptions::new();
+    options.max_total_words(0);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    match message.init_root::<primitive_list::Builder<u64>>() {
+        Err(::Error::ResourceLimit { .. }) => {}
+        _ => panic!("expected a resource limit error"),
+    }
+    assert!(message.get_orphanage().is_err());
+}
+
+#[test]
+fn canonicalize_struct() {
+    use message::{canonicalize, AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder,
+                  MessageReader, ReaderOptions, SegmentArrayMessageReader};
+    use private::layout::{PointerReader, StructSize};
+    use std::str::Str;
+
+    // Small segments force far pointers, which canonical form does not allow.
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(4).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut message = MallocMessageBuilder::new(options).unwrap();
+    {
+        // Two data words of which only the first is used, and three pointers of which only the
+        // first two are set.
+        let builder = get_root(&mut message).init_struct(StructSize { data : 2, pointers : 3 }).unwrap();
+        builder.set_data_field::<u32>(0, 0x1234);
+        builder.get_pointer_field(0).set_text("canonical").unwrap();
+        let list = builder.get_pointer_field(1)
+            .init_struct_list(2, StructSize { data : 2, pointers : 1 }).unwrap();
+        list.get_struct_element(0).set_data_field::<u64>(0, 5);
+        list.get_struct_element(1).set_data_field::<u64>(0, 6);
+    }
+    assert!(message.arena().more_segments.len() > 0);
+
+    let canonical = message.get_segments_for_output(|segments| {
+        let reader = SegmentArrayMessageReader::new(segments, ReaderOptions::new());
+        assert!(!reader.is_canonical().unwrap());
+        let segment = &reader.arena().segment0;
+        let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+        canonicalize(&root.get_struct(::std::ptr::null()).unwrap()).unwrap()
+    });
+
+    // Root pointer, one data word, two pointers, the text, and the list tag plus two
+    // one-word elements.
+    assert_eq!(canonical.len(), 1 + 3 + 2 + 1 + 2);
+
+    let segments = [canonical.as_slice()];
+    let reader = SegmentArrayMessageReader::new(&segments, ReaderOptions::new());
+    assert!(reader.is_canonical().unwrap());
+
+    let segment = &reader.arena().segment0;
+    let root = PointerReader::get_root(segment, unsafe { segment.get_start_ptr() }, 64).unwrap();
+    let root = root.get_struct(::std::ptr::null()).unwrap();
+    assert_eq!(root.get_data_field::<u32>(0), 0x1234);
+    assert_eq!(root.get_pointer_field(0).get_text(::std::ptr::null(), 0).unwrap().as_slice(), "canonical");
+    assert!(root.get_pointer_field(2).is_null());
+
+    // Canonicalizing a canonical message changes nothing.
+    let again = canonicalize(&root).unwrap();
+    assert!(::Word::words_to_bytes(again.as_slice()) == ::Word::words_to_bytes(canonical.as_slice()));
+}
+
+#[test]
+fn canonicalize_small_struct_lists() {
+    use message::{canonicalize, MallocMessageBuilder, MessageReader, ReaderOptions,
+                  SegmentArrayMessageReader};
+    use private::layout::StructSize;
+
+    // Canonical form keeps struct lists inline-composite even when each element would fit in a
+    // primitive or pointer list. The list pointer is the second word, and its element size is
+    // the low three bits of its upper half.
+    let mut message = MallocMessageBuilder::new_default();
+    let root = get_root(&mut message).init_struct(StructSize { data : 0, pointers : 1 }).unwrap();
+    let list = root.get_pointer_field(0).init_struct_list(2, StructSize { data : 1, pointers : 0 }).unwrap();
+    list.get_struct_element(0).set_data_field::<u64>(0, 5);
+    list.get_struct_element(1).set_data_field::<u64>(0, 6);
+    let canonical = canonicalize(&root.as_reader()).unwrap();
+    assert_eq!(canonical.len(), 1 + 1 + 1 + 2);
+    assert_eq!(::Word::words_to_bytes(canonical.as_slice())[12] & 7, 7);
+    let segments = [canonical.as_slice()];
+    assert!(SegmentArrayMessageReader::new(&segments, ReaderOptions::new()).is_canonical().unwrap());
+
+    let mut message = MallocMessageBuilder::new_default();
+    let root = get_root(&mut message).init_struct(StructSize { data : 0, pointers : 1 }).unwrap();
+    let list = root.get_pointer_field(0).init_struct_list(2, StructSize { data : 0, pointers : 1 }).unwrap();
+    list.get_struct_element(0).get_pointer_field(0).set_text("a").unwrap();
+    list.get_struct_element(1).get_pointer_field(0).set_text("b").unwrap();
+    let canonical = canonicalize(&root.as_reader()).unwrap();
+    assert_eq!(canonical.len(), 1 + 1 + 1 + 2 + 2);
+    assert_eq!(::Word::words_to_bytes(canonical.as_slice())[12] & 7, 7);
+    let segments = [canonical.as_slice()];
+    assert!(SegmentArrayMessageReader::new(&segments, ReaderOptions::new()).is_canonical().unwrap());
+}
+
+#[test]
+fn canonicalize_empty_structs() {
+    use message::{canonicalize, MallocMessageBuilder, MessageReader, ReaderOptions,
+                  SegmentArrayMessageReader};
+    use private::layout::StructSize;
+
+    let mut message = MallocMessageBuilder::new_default();
+    let empty = get_root(&mut message).init_struct(StructSize { data : 0, pointers : 0 }).unwrap();
+    let canonical = canonicalize(&empty.as_reader()).unwrap();
+    assert_eq!(canonical.len(), 1);
+    let segments = [canonical.as_slice()];
+    assert!(SegmentArrayMessageReader::new(&segments, ReaderOptions::new()).is_canonical().unwrap());
+
+    // A struct whose only field is an empty struct.
+    let mut message = MallocMessageBuilder::new_default();
+    let outer = get_root(&mut message).init_struct(StructSize { data : 0, pointers : 1 }).unwrap();
+    outer.get_pointer_field(0).init_struct(StructSize { data : 0, pointers : 0 }).unwrap();
+    let canonical = canonicalize(&outer.as_reader()).unwrap();
+    assert_eq!(canonical.len(), 2);
+    let segments = [canonical.as_slice()];
+    assert!(SegmentArrayMessageReader::new(&segments, ReaderOptions::new()).is_canonical().unwrap());
+}
+
+#[test]
+fn any_pointer_equality_and_hash() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder};
+    use private::layout::StructSize;
+
+    // Same value, different layouts: `a` lives in one big segment, while `b` has a wider struct
+    // with a trailing zero data word and a trailing null pointer, and is split across segments.
+    let mut a = MallocMessageBuilder::new_default();
+    {
+        let root = get_root(&mut a).init_struct(StructSize { data : 1, pointers : 1 }).unwrap();
+        root.set_data_field::<u64>(0, 42);
+        root.get_pointer_field(0).set_text("same").unwrap();
+    }
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(2).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut b = MallocMessageBuilder::new(options).unwrap();
+    {
+        let root = get_root(&mut b).init_struct(StructSize { data : 2, pointers : 2 }).unwrap();
+        root.set_data_field::<u64>(0, 42);
+        root.get_pointer_field(0).set_text("same").unwrap();
+    }
+    assert!(b.arena().more_segments.len() > 0);
+
+    let a_root = a.get_root_internal().unwrap().as_reader();
+    let b_root = b.get_root_internal().unwrap().as_reader();
+    assert!(a_root.equals(&b_root).unwrap());
+    assert_eq!(a_root.hash().unwrap(), b_root.hash().unwrap());
+
+    get_root(&mut b).get_struct(StructSize { data : 2, pointers : 2 }, ::std::ptr::null())
+        .unwrap().get_pointer_field(0).set_text("different").unwrap();
+    let b_root = b.get_root_internal().unwrap().as_reader();
+    assert!(!a_root.equals(&b_root).unwrap());
+    assert!(a_root.hash().unwrap() != b_root.hash().unwrap());
+}
+
+#[test]
+fn list_equality_across_layouts() {
+    use private::layout::{FourBytes, PrimitiveElement, StructSize, TwoBytes};
+
+    // The same struct list values, written with a narrower and a wider element type.
+    let mut a = MallocMessageBuilder::new_default();
+    {
+        let list = get_root(&mut a).init_struct_list(2, StructSize { data : 1, pointers : 0 }).unwrap();
+        list.get_struct_element(0).set_data_field::<u32>(0, 5);
+        list.get_struct_element(1).set_data_field::<u32>(0, 6);
+    }
+    let mut b = MallocMessageBuilder::new_default();
+    {
+        let list = get_root(&mut b).init_struct_list(2, StructSize { data : 2, pointers : 1 }).unwrap();
+        list.get_struct_element(0).set_data_field::<u32>(0, 5);
+        list.get_struct_element(1).set_data_field::<u32>(0, 6);
+    }
+    let a_root = get_root(&mut a).as_reader();
+    let b_root = get_root(&mut b).as_reader();
+    assert!(a_root.equals(&b_root).unwrap());
+    assert_eq!(a_root.hash().unwrap(), b_root.hash().unwrap());
+
+    // Lists of elements smaller than a word must agree on the element size.
+    let mut c = MallocMessageBuilder::new_default();
+    {
+        let list = get_root(&mut c).init_list(FourBytes, 2).unwrap();
+        PrimitiveElement::set(&list, 0, 5u32);
+        PrimitiveElement::set(&list, 1, 6u32);
+    }
+    let mut d = MallocMessageBuilder::new_default();
+    {
+        let list = get_root(&mut d).init_list(TwoBytes, 2).unwrap();
+        PrimitiveElement::set(&list, 0, 5u16);
+        PrimitiveElement::set(&list, 1, 6u16);
+    }
+    let c_root = get_root(&mut c).as_reader();
+    let d_root = get_root(&mut d).as_reader();
+    assert!(c_root.equals(&c_root).unwrap());
+    assert!(!c_root.equals(&d_root).unwrap());
+    assert!(!c_root.equals(&a_root).unwrap());
+}
+
+#[test]
+fn compact_message() {
+    use message::{MallocMessageBuilder, MessageBuilder};
+    use std::str::Str;
+    use text;
+    use text_list;
+
+    let mut message = MallocMessageBuilder::new_default();
+    {
+        let mut list = message.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(2).unwrap();
+        list.set(0, "kept").unwrap();
+        list.set(1, "overwritten, many times over").unwrap();
+    }
+    for _ in 0..10 {
+        let mut list = message.get_root::<text_list::Builder>().unwrap();
+        list.set(1, "overwritten, many times over").unwrap();
+    }
+
+    let words_before = message.arena().words_used();
+    let reclaimed = message.compact().unwrap();
+    assert!(reclaimed > 0);
+    assert_eq!(message.arena().words_used(), words_before - reclaimed);
+    assert_eq!(message.arena().more_segments.len(), 0);
+
+    {
+        let kept : text::Builder = message.get_root::<text_list::Builder>().unwrap().get(0).unwrap();
+        assert_eq!(kept.as_slice(), "kept");
+    }
+    let overwritten : text::Builder = message.get_root::<text_list::Builder>().unwrap().get(1).unwrap();
+    assert_eq!(overwritten.as_slice(), "overwritten, many times over");
+}
+
+#[test]
+fn typed_builder_and_reader() {
+    use message::{ReaderOptions, TypedBuilder, TypedReader};
+    use primitive_list;
+    use std::str::Str;
+    use text_list;
+
+    fn assert_send<T : Send>(_ : &T) {}
+
+    let mut builder : TypedBuilder<text_list::Owned> = TypedBuilder::new_default();
+    {
+        let mut list = builder.init_root_sized(2).unwrap();
+        list.set(0, "hello").unwrap();
+        list.set(1, "world").unwrap();
+    }
+    assert_eq!(builder.get_root().unwrap().get(1).unwrap().as_slice(), "world");
+
+    let reader : TypedReader<text_list::Owned> = builder.into_reader(ReaderOptions::new()).unwrap();
+    assert_send(&reader);
+    {
+        let list = reader.get_root().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.get(0).unwrap(), "hello");
+    }
+
+    let mut builder = reader.into_builder().unwrap();
+    builder.get_root().unwrap().set(0, "goodbye").unwrap();
+    assert_eq!(builder.get_root().unwrap().get(0).unwrap().as_slice(), "goodbye");
+    assert_eq!(builder.get_root().unwrap().get(1).unwrap().as_slice(), "world");
+
+    // Generic list types have markers too.
+    let mut builder : TypedBuilder<primitive_list::Owned<u32>> = TypedBuilder::new_default();
+    builder.init_root_sized(3).unwrap().set(2, 7);
+    let reader = builder.into_reader(ReaderOptions::new()).unwrap();
+    assert_eq!(reader.get_root().unwrap().get(2), 7);
+}
+
+#[test]
+fn size_hinted_builder() {
+    use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder};
+    use text_list;
+
+    let mut options = BuilderOptions::new();
+    options.first_segment_words(4).allocation_strategy(AllocationStrategy::FixedSize);
+    let mut source = MallocMessageBuilder::new(options).unwrap();
+    {
+        let mut list = source.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(3).unwrap();
+        list.set(0, "spread").unwrap();
+        list.set(1, "across").unwrap();
+        list.set(2, "several small segments").unwrap();
+    }
+    assert!(source.arena().more_segments.len() > 0);
+
+    let root = source.get_root_internal().unwrap().as_reader();
+    let mut copy = MallocMessageBuilder::with_size_hint(root.total_size().unwrap());
+    copy.get_root_internal().unwrap().set_as_copy_of(root).unwrap();
+    assert_eq!(copy.arena().more_segments.len(), 0);
+    assert_eq!(copy.arena().segment0.current_size(), copy.arena().segment0.reader.size);
+    assert!(copy.get_root_internal().unwrap().as_reader().equals(&root).unwrap());
+}
+
+#[test]
+fn copy_capabilities_between_messages() {
+    use any_pointer;
+    use capability::Request;
+    use message::{MallocMessageBuilder, MessageBuilder, ReaderOptions, TypedBuilder};
+    use private::capability::{CallContextHook, ClientHook, new_broken_cap};
+    use private::layout::StructSize;
+    use MessageSize;
+
+    struct TestCap { id : u32 }
+
+    impl ClientHook for TestCap {
+        fn copy(&self) -> Box<ClientHook+Send> { Box::new(TestCap { id : self.id }) }
+        fn new_call(&self, interface_id : u64, method_id : u16, size_hint : Option<MessageSize>)
+                    -> Request<any_pointer::Builder, any_pointer::Reader, any_pointer::Pipeline> {
+            new_broken_cap("test").new_call(interface_id, method_id, size_hint)
+        }
+        fn call(&self, _interface_id : u64, _method_id : u16, context : Box<CallContextHook+Send>) {
+            context.fail("test".to_string());
+        }
+        fn get_descriptor(&self) -> Box<::std::any::Any + 'static> { Box::new(self.id) }
+    }
+
+    fn cap_id(cap : Box<ClientHook+Send>) -> u32 {
+        *cap.get_descriptor().downcast_ref::<u32>().unwrap()
+    }
+
+    // The source's cap table has an unrelated entry first, so the copied pointers can only be
+    // right if their indexes were rewritten.
+    let mut source = MallocMessageBuilder::new_default();
+    source.mut_arena().inject_cap(Box::new(TestCap { id : 0 }));
+    {
+        let root = get_root(&mut source).init_struct(StructSize { data : 0, pointers : 2 }).unwrap();
+        root.get_pointer_field(0).set_capability(Box::new(TestCap { id : 1 }));
+        let list = root.get_pointer_field(1).init_struct_list(1, StructSize { data : 1, pointers : 1 }).unwrap();
+        list.get_struct_element(0).get_pointer_field(0).set_capability(Box::new(TestCap { id : 2 }));
+    }
+    let source_root = get_root(&mut source).as_reader();
+
+    let mut copy = MallocMessageBuilder::new_default();
+    get_root(&mut copy).copy_from(source_root).unwrap();
+    assert_eq!(copy.get_cap_table().len(), 2);
+
+    let root = get_root(&mut copy).as_reader().get_struct(::std::ptr::null()).unwrap();
+    assert_eq!(cap_id(root.get_pointer_field(0).get_capability()), 1);
+    let list = root.get_pointer_field(1).get_list(::private::layout::InlineComposite, ::std::ptr::null()).unwrap();
+    assert_eq!(cap_id(list.get_struct_element(0).get_pointer_field(0).get_capability()), 2);
+
+    // Converting a typed builder into a reader keeps its capabilities.
+    let mut builder : TypedBuilder<any_pointer::Owned> = TypedBuilder::new_default();
+    builder.init_root().unwrap().set_as_capability(Box::new(TestCap { id : 3 }));
+    let reader = builder.into_reader(ReaderOptions::new()).unwrap();
+    assert_eq!(cap_id(reader.get_root().unwrap().get_pipelined_cap(&[]).unwrap()), 3);
+}
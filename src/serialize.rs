@@ -47,17 +47,15 @@ impl MessageReader for OwnedSpaceMessageReader {
     }
 }
 
-fn invalid_input<T>(desc : &'static str) -> ::std::old_io::IoResult<T> {
-    return Err(::std::old_io::IoError{ kind : ::std::old_io::InvalidInput,
-                                       desc : desc,
-                                       detail : None});
-}
+// The arena only points into `owned_space`, which moves along with the reader.
+unsafe impl Send for OwnedSpaceMessageReader {}
 
-pub fn new_reader<U : ::std::old_io::Reader>(input_stream : &mut U,
-                                             options : ReaderOptions)
-                                             -> ::std::old_io::IoResult<OwnedSpaceMessageReader> {
+pub fn read_message<U : ::std::io::Read>(input_stream : &mut U,
+                                         options : ReaderOptions)
+                                         -> ::Result<OwnedSpaceMessageReader> {
 
-    let first_word = try!(input_stream.read_exact(8));
+    let mut first_word : [u8; 8] = [0; 8];
+    try!(io::read_exact(input_stream, &mut first_word));
 
     let segment_count : u32 =
         unsafe {let p : *const WireValue<u32> = ::std::mem::transmute(first_word.as_ptr());
@@ -74,13 +72,15 @@ pub fn new_reader<U : ::std::old_io::Reader>(input_stream : &mut U,
     let mut total_words = segment0_size;
 
     if segment_count >= 512 {
-        return invalid_input("too many segments");
+        return Err(::Error::new_decode_error("Too many segments.", Some(format!("{}", segment_count))));
     }
 
     let mut more_sizes : Vec<u32> = Vec::with_capacity((segment_count & !1) as usize);
 
     if segment_count > 1 {
-        let more_sizes_raw = try!(input_stream.read_exact((4 * (segment_count & !1)) as usize));
+        let raw_len = (4 * (segment_count & !1)) as usize;
+        let mut more_sizes_raw : Vec<u8> = ::std::iter::repeat(0u8).take(raw_len).collect();
+        try!(io::read_exact(input_stream, more_sizes_raw.as_mut_slice()));
         for ii in 0..(segment_count as usize - 1) {
             let size = unsafe {
                 let p : *const WireValue<u32> =
@@ -98,8 +98,9 @@ pub fn new_reader<U : ::std::old_io::Reader>(input_stream : &mut U,
     //# segment size to make the receiver allocate excessive space
     //# and possibly crash.
     if ! (total_words as u64 <= options.traversal_limit_in_words)  {
-        return invalid_input("Message is too large. To increase the limit on the \
-                              receiving end, see capnp::ReaderOptions.");
+        return Err(::Error::new_decode_error(
+            "Message is too large. To increase the limit on the receiving end, see capnp::ReaderOptions.",
+            None));
     }
 
     let mut owned_space : Vec<Word> = Word::allocate_zeroed_vec(total_words as usize);
@@ -108,7 +109,7 @@ pub fn new_reader<U : ::std::old_io::Reader>(input_stream : &mut U,
     unsafe {
         let ptr : *mut u8 = ::std::mem::transmute(owned_space.as_mut_slice().as_mut_ptr());
         let buf = ::std::slice::from_raw_parts_mut::<u8>(ptr, buf_len);
-        try!(io::read_at_least(input_stream, buf, buf_len));
+        try!(io::read_exact(input_stream, buf));
     }
 
     // TODO(maybe someday) lazy reading like in capnp-c++?
@@ -141,34 +142,205 @@ pub fn new_reader<U : ::std::old_io::Reader>(input_stream : &mut U,
     })
 }
 
+/// A message reader whose segments are borrowed directly from a buffer holding a serialized
+/// message, so that no copy is made.
+pub struct BorrowedSpaceMessageReader<'a> {
+    options : ReaderOptions,
+    arena : Box<arena::ReaderArena>,
+    segment_slices : Vec<(usize, usize)>,
+    words : &'a [Word],
+}
 
-pub fn write_message<T : ::std::old_io::Writer, U : MessageBuilder>(
-    output_stream : &mut T,
-    message : &U) -> ::std::old_io::IoResult<()> {
+impl <'a> MessageReader for BorrowedSpaceMessageReader<'a> {
+    fn get_segment(&self, id : usize) -> &[Word] {
+        let (a,b) = self.segment_slices.as_slice()[id];
+        &self.words[a .. b]
+    }
 
-    try!(message.get_segments_for_output(
-        |segments| {
+    fn arena(&self) -> &arena::ReaderArena { &*self.arena }
+    fn mut_arena(&mut self) -> &mut arena::ReaderArena { &mut *self.arena }
 
-            let table_size : usize = (segments.len() + 2) & (!1);
+    fn get_options(&self) -> &ReaderOptions {
+        return &self.options;
+    }
+}
 
-            let mut table : Vec<WireValue<u32>> = Vec::with_capacity(table_size);
-            unsafe { table.set_len(table_size) }
+impl <'a> BorrowedSpaceMessageReader<'a> {
+    /// Returns how many words of the buffer the message occupies, including the segment table.
+    /// Any words after that are not part of this message.
+    pub fn words_consumed(&self) -> usize {
+        match self.segment_slices.last() {
+            Some(&(_, end)) => end,
+            None => 0,
+        }
+    }
+}
 
-            table.as_mut_slice()[0].set((segments.len() - 1) as u32);
+// Parses the segment table at the start of `words` and returns the range of words occupied by
+// each segment.
+fn read_segment_table(words : &[Word], options : ReaderOptions) -> ::Result<Vec<(usize, usize)>> {
+    if words.len() == 0 {
+        return Err(::Error::new_decode_error("Message ends prematurely in first word.", None));
+    }
 
-            for i in 0..segments.len() {
-                table.as_mut_slice()[i + 1].set(segments[i].len() as u32);
-            }
-            if segments.len() % 2 == 0 {
-                // Set padding.
-                table.as_mut_slice()[segments.len() + 1].set( 0 );
-            }
+    let table : *const WireValue<u32> = unsafe { ::std::mem::transmute(words.as_ptr()) };
+    let segment_count = unsafe { (*table).get() } as u64 + 1;
+    if segment_count >= 512 {
+        return Err(::Error::new_decode_error("Too many segments.", Some(format!("{}", segment_count))));
+    }
+
+    let table_words = segment_table_size_in_words(segment_count as usize);
+    if words.len() < table_words {
+        return Err(::Error::new_decode_error("Message ends prematurely in segment table.", None));
+    }
+
+    let mut segment_slices : Vec<(usize, usize)> = Vec::with_capacity(segment_count as usize);
+    let mut offset = table_words as u64;
+    for i in 0..segment_count as isize {
+        let size = unsafe { (*table.offset(i + 1)).get() } as u64;
+        segment_slices.push((offset as usize, (offset + size) as usize));
+        offset += size;
+    }
+
+    //# Don't accept a message which the receiver couldn't possibly
+    //# traverse without hitting the traversal limit.
+    if offset - table_words as u64 > options.traversal_limit_in_words {
+        return Err(::Error::new_decode_error(
+            "Message is too large. To increase the limit on the receiving end, see capnp::ReaderOptions.",
+            None));
+    }
+    if (words.len() as u64) < offset {
+        return Err(::Error::new_decode_error("Message ends prematurely.",
+                                             Some(format!("expected {} words, got {}",
+                                                          offset, words.len()))));
+    }
+
+    Ok(segment_slices)
+}
+
+/// Parses the segment table at the start of `words` and returns a reader whose segments point
+/// into `words`. Returns an error if `words` is too short to hold the message that the segment
+/// table describes.
+pub fn read_message_from_words<'a>(words : &'a [Word],
+                                   options : ReaderOptions) -> ::Result<BorrowedSpaceMessageReader<'a>> {
+    let segment_slices = try!(read_segment_table(words, options));
+
+    let arena = {
+        let segments : Vec<&[Word]> =
+            segment_slices.iter().map(|&(a, b)| &words[a .. b]).collect();
+        arena::ReaderArena::new(segments.as_slice(), options)
+    };
 
-            unsafe {
-                let ptr : *const u8 = ::std::mem::transmute(table.as_ptr());
-                let buf = ::std::slice::from_raw_parts::<u8>(ptr, table.len() * 4);
-                try!(output_stream.write_all(buf));
+    Ok(BorrowedSpaceMessageReader {
+        options : options,
+        arena : arena,
+        segment_slices : segment_slices,
+        words : words,
+    })
+}
+
+/// Like `read_message_from_words()`, but takes ownership of the buffer, so that the returned
+/// reader can outlive the scope in which the message was produced.
+pub fn read_message_from_owned_words(words : Vec<Word>,
+                                     options : ReaderOptions) -> ::Result<OwnedSpaceMessageReader> {
+    let segment_slices = try!(read_segment_table(words.as_slice(), options));
+
+    let arena = {
+        let segments : Vec<&[Word]> =
+            segment_slices.iter().map(|&(a, b)| &words[a .. b]).collect();
+        arena::ReaderArena::new(segments.as_slice(), options)
+    };
+
+    Ok(OwnedSpaceMessageReader {
+        segment_slices : segment_slices,
+        owned_space : words,
+        arena : arena,
+        options : options,
+    })
+}
+
+/// Like `read_message_from_words()`, but for a byte buffer. The buffer must be aligned to a word
+/// boundary.
+pub fn read_message_from_bytes<'a>(bytes : &'a [u8],
+                                   options : ReaderOptions) -> ::Result<BorrowedSpaceMessageReader<'a>> {
+    if bytes.as_ptr() as usize % BYTES_PER_WORD != 0 {
+        return Err(::Error::new_decode_error("Message buffer is not aligned to a word boundary.", None));
+    }
+    if bytes.len() % BYTES_PER_WORD != 0 {
+        return Err(::Error::new_decode_error("Message buffer is not a whole number of words.", None));
+    }
+    read_message_from_words(Word::bytes_to_words(bytes), options)
+}
+
+fn segment_table_size_in_words(segment_count : usize) -> usize {
+    //# The table holds the segment count and then one size per segment, padded to a whole
+    //# number of words.
+    segment_count / 2 + 1
+}
+
+/// Encodes the segment table that precedes `segments` in a serialized message: the segment count
+/// minus one, then the size of each segment in words, padded with zeros to a whole number of
+/// words.
+pub fn encode_segment_table(segments : &[&[Word]]) -> Vec<Word> {
+    let mut table = Word::allocate_zeroed_vec(segment_table_size_in_words(segments.len()));
+    unsafe {
+        let entries : *mut WireValue<u32> = ::std::mem::transmute(table.as_mut_slice().as_mut_ptr());
+        (*entries).set((segments.len() - 1) as u32);
+        for i in 0..segments.len() {
+            (*entries.offset(i as isize + 1)).set(segments[i].len() as u32);
+        }
+    }
+    table
+}
+
+/// Returns the number of words that `write_message_to_words()` produces for `message`,
+/// including the segment table.
+pub fn compute_serialized_size_in_words<U : MessageBuilder>(message : &U) -> usize {
+    message.get_segments_for_output(|segments| {
+        let mut size = segment_table_size_in_words(segments.len());
+        for segment in segments.iter() {
+            size += segment.len();
+        }
+        size
+    })
+}
+
+/// Returns the number of bytes that `write_message()` writes for `message`.
+pub fn compute_serialized_size_in_bytes<U : MessageBuilder>(message : &U) -> usize {
+    compute_serialized_size_in_words(message) * BYTES_PER_WORD
+}
+
+/// Serializes `message` into a single, word-aligned allocation holding the segment table
+/// followed by the segments, exactly as `write_message()` would write them.
+pub fn write_message_to_words<U : MessageBuilder>(message : &U) -> Vec<Word> {
+    let mut result = Word::allocate_zeroed_vec(compute_serialized_size_in_words(message));
+    message.get_segments_for_output(|segments| {
+        let table = encode_segment_table(segments);
+        unsafe {
+            ::std::ptr::copy_nonoverlapping_memory(result.as_mut_slice().as_mut_ptr(),
+                                                   table.as_ptr(), table.len());
+
+            let mut offset = table.len();
+            for segment in segments.iter() {
+                ::std::ptr::copy_nonoverlapping_memory(
+                    result.as_mut_slice().as_mut_ptr().offset(offset as isize),
+                    segment.as_ptr(), segment.len());
+                offset += segment.len();
             }
+        }
+    });
+    result
+}
+
+
+pub fn write_message<T : ::std::io::Write, U : MessageBuilder>(
+    output_stream : &mut T,
+    message : &U) -> ::std::io::Result<()> {
+
+    try!(message.get_segments_for_output(
+        |segments| {
+            let table = encode_segment_table(segments);
+            try!(output_stream.write_all(Word::words_to_bytes(table.as_slice())));
 
             for i in 0..segments.len() {
                 unsafe {
@@ -182,3 +354,116 @@ pub fn write_message<T : ::std::old_io::Writer, U : MessageBuilder>(
 
     output_stream.flush()
 }
+
+#[cfg(test)]
+mod tests {
+    use message::{MallocMessageBuilder, MessageBuilder, MessageReader, ReaderOptions};
+    use serialize::{compute_serialized_size_in_bytes, compute_serialized_size_in_words,
+                    read_message_from_bytes, read_message_from_words,
+                    write_message, write_message_to_words};
+    use text_list;
+    use Word;
+
+    #[test]
+    fn read_borrowed_message() {
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut list = message.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(2).unwrap();
+            list.set(0, "borrowed").unwrap();
+            list.set(1, "not copied").unwrap();
+        }
+
+        let mut bytes : Vec<u8> = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+        let mut words = Word::allocate_zeroed_vec(bytes.len() / 8);
+        unsafe {
+            ::std::ptr::copy_nonoverlapping_memory(words.as_mut_slice().as_mut_ptr() as *mut u8,
+                                                   bytes.as_ptr(), bytes.len());
+        }
+
+        {
+            let reader = read_message_from_words(words.as_slice(), ReaderOptions::new()).unwrap();
+            assert_eq!(reader.words_consumed(), words.len());
+            let list = reader.get_root::<text_list::Reader>().unwrap();
+            assert_eq!(list.get(0).unwrap(), "borrowed");
+            assert_eq!(list.get(1).unwrap(), "not copied");
+        }
+
+        assert!(read_message_from_words(&words[.. words.len() - 1], ReaderOptions::new()).is_err());
+        assert!(read_message_from_bytes(&Word::words_to_bytes(words.as_slice())[1 ..],
+                                        ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn write_flat_words() {
+        use message::{AllocationStrategy, BuilderOptions};
+
+        // Several segments, so that the segment table needs padding in one case or the other.
+        let mut options = BuilderOptions::new();
+        options.first_segment_words(2).allocation_strategy(AllocationStrategy::FixedSize);
+        let mut message = MallocMessageBuilder::new(options).unwrap();
+        {
+            let mut list = message.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(3).unwrap();
+            list.set(0, "one").unwrap();
+            list.set(1, "two").unwrap();
+            list.set(2, "three").unwrap();
+        }
+        assert!(message.arena().more_segments.len() > 0);
+
+        let words = write_message_to_words(&message);
+        assert_eq!(words.len(), compute_serialized_size_in_words(&message));
+
+        let mut bytes : Vec<u8> = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+        assert_eq!(bytes.len(), compute_serialized_size_in_bytes(&message));
+        assert!(Word::words_to_bytes(words.as_slice()) == bytes.as_slice());
+
+        let reader = read_message_from_words(words.as_slice(), ReaderOptions::new()).unwrap();
+        let list = reader.get_root::<text_list::Reader>().unwrap();
+        assert_eq!(list.get(2).unwrap(), "three");
+    }
+
+    #[test]
+    fn read_message_from_std_reader() {
+        use serialize::read_message;
+
+        let mut message = MallocMessageBuilder::new_default();
+        {
+            let mut list = message.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(1).unwrap();
+            list.set(0, "through std::io").unwrap();
+        }
+        let mut bytes : Vec<u8> = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+
+        {
+            let mut input : &[u8] = bytes.as_slice();
+            let reader = read_message(&mut input, ReaderOptions::new()).unwrap();
+            assert_eq!(reader.get_root::<text_list::Reader>().unwrap().get(0).unwrap(), "through std::io");
+            assert_eq!(input.len(), 0);
+        }
+
+        let mut truncated : &[u8] = &bytes[.. bytes.len() - 8];
+        assert!(read_message(&mut truncated, ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn buffered_input_stream_short_reads_at_eof() {
+        use io::BufferedInputStreamWrapper;
+        use std::io::Read;
+
+        let bytes = [1u8, 2, 3];
+        let mut input : &[u8] = &bytes;
+        let mut buffered = BufferedInputStreamWrapper::new(&mut input);
+        let mut buf = [0u8; 8];
+        assert_eq!(buffered.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf[2], 3);
+        assert_eq!(buffered.read(&mut buf).unwrap(), 0);
+
+        // Reads bigger than the buffer go straight to the underlying stream.
+        let mut input : &[u8] = &bytes;
+        let mut buffered = BufferedInputStreamWrapper::new(&mut input);
+        let mut big = ::std::iter::repeat(0u8).take(10000).collect::<Vec<u8>>();
+        assert_eq!(buffered.read(big.as_mut_slice()).unwrap(), 3);
+        assert_eq!(buffered.read(big.as_mut_slice()).unwrap(), 0);
+    }
+}
@@ -22,6 +22,7 @@
 use io;
 use message::*;
 use serialize;
+use Word;
 
 trait PtrUsize<T> {
     fn as_usize(self) -> usize;
@@ -58,13 +59,16 @@ macro_rules! refresh_buffer(
             $in_end = e;
             $size = ptr_sub($in_end, $in_ptr);
             $buffer_begin = b;
-            assert!($size > 0);
+            if $size == 0 {
+                return Err(::std::io::Error::new(::std::io::ErrorKind::Other,
+                                                 "Packed input ended in the middle of a word.", None));
+            }
         }
         );
     );
 
-impl <'a, R : io::BufferedInputStream> ::std::old_io::Reader for PackedInputStream<'a, R> {
-    fn read(&mut self, out_buf: &mut [u8]) -> ::std::old_io::IoResult<usize> {
+impl <'a, R : io::BufferedInputStream> ::std::io::Read for PackedInputStream<'a, R> {
+    fn read(&mut self, out_buf: &mut [u8]) -> ::std::io::Result<usize> {
         let len = out_buf.len();
 
         if len == 0 { return Ok(0); }
@@ -174,7 +178,7 @@ impl <'a, R : io::BufferedInputStream> ::std::old_io::Reader for PackedInputStre
                         try!(self.inner.skip(size));
                         {
                             let buf = ::std::slice::from_raw_parts_mut::<u8>(out, run_length);
-                            try!(self.inner.read(buf));
+                            try!(io::read_exact(self.inner, buf));
                         }
 
                         out = out.offset(run_length as isize);
@@ -203,24 +207,24 @@ impl <'a, R : io::BufferedInputStream> ::std::old_io::Reader for PackedInputStre
 
 
 
-pub fn new_reader<U : io::BufferedInputStream>(input : &mut U,
-                                               options : ReaderOptions)
-                                               -> ::std::old_io::IoResult<serialize::OwnedSpaceMessageReader> {
+pub fn read_packed_message<U : io::BufferedInputStream>(input : &mut U,
+                                                        options : ReaderOptions)
+                                                        -> ::Result<serialize::OwnedSpaceMessageReader> {
     let mut packed_input = PackedInputStream {
         inner : input
     };
 
-    serialize::new_reader(&mut packed_input, options)
+    serialize::read_message(&mut packed_input, options)
 }
 
-pub fn new_reader_unbuffered<U : ::std::old_io::Reader>(input : &mut U,
-                                                        options : ReaderOptions)
-                                                  -> ::std::old_io::IoResult<serialize::OwnedSpaceMessageReader> {
+pub fn read_packed_message_unbuffered<U : ::std::io::Read>(input : &mut U,
+                                                           options : ReaderOptions)
+                                                           -> ::Result<serialize::OwnedSpaceMessageReader> {
     let mut packed_input = PackedInputStream {
         inner : &mut io::BufferedInputStreamWrapper::new(input)
     };
 
-    serialize::new_reader(&mut packed_input, options)
+    serialize::read_message(&mut packed_input, options)
 }
 
 
@@ -228,8 +232,8 @@ pub struct PackedOutputStream<'a, W:'a> {
     pub inner : &'a mut W
 }
 
-impl <'a, W : io::BufferedOutputStream> ::std::old_io::Writer for PackedOutputStream<'a, W> {
-    fn write_all(&mut self, in_buf : &[u8]) -> ::std::old_io::IoResult<()> {
+impl <'a, W : io::BufferedOutputStream> ::std::io::Write for PackedOutputStream<'a, W> {
+    fn write(&mut self, in_buf : &[u8]) -> ::std::io::Result<usize> {
         unsafe {
             let (mut out, mut buffer_end) = self.inner.get_write_buffer();
             let mut buffer_begin = out;
@@ -378,22 +382,80 @@ impl <'a, W : io::BufferedOutputStream> ::std::old_io::Writer for PackedOutputSt
             }
 
             try!(self.inner.write_ptr(buffer_begin, ptr_sub(out, buffer_begin)));
-            Ok(())
+            Ok(in_buf.len())
         }
     }
 
-   fn flush(&mut self) -> ::std::old_io::IoResult<()> { self.inner.flush() }
+   fn flush(&mut self) -> ::std::io::Result<()> { self.inner.flush() }
+}
+
+// Returns the number of bytes that a single `PackedOutputStream::write()` call produces for
+// `in_buf`, by running the same packing algorithm without writing anything.
+fn compute_packed_size(in_buf : &[u8]) -> usize {
+    let in_end = in_buf.len();
+    let mut in_pos = 0;
+    let mut size = 0;
+
+    while in_pos < in_end {
+        let nonzero = in_buf[in_pos .. in_pos + 8].iter().filter(|&&b| b != 0).count();
+        in_pos += 8;
+
+        // Tag byte, followed by the nonzero bytes.
+        size += 1 + nonzero;
+
+        if nonzero == 0 {
+            // Count of consecutive zero words.
+            let mut limit = in_end;
+            if (limit - in_pos) / 8 > 255 {
+                limit = in_pos + 255 * 8;
+            }
+            while in_pos < limit && in_buf[in_pos .. in_pos + 8].iter().all(|&b| b == 0) {
+                in_pos += 8;
+            }
+            size += 1;
+        } else if nonzero == 8 {
+            // Count of uncompressed words, followed by the words themselves.
+            let run_start = in_pos;
+            let mut limit = in_end;
+            if limit - in_pos > 255 * 8 {
+                limit = in_pos + 255 * 8;
+            }
+            while in_pos < limit {
+                let zeros = in_buf[in_pos .. in_pos + 8].iter().filter(|&&b| b == 0).count();
+                if zeros >= 2 {
+                    break;
+                }
+                in_pos += 8;
+            }
+            size += 1 + (in_pos - run_start);
+        }
+    }
+    size
+}
+
+/// Returns the number of bytes that `write_packed_message()` writes for `message`, without
+/// allocating an output buffer.
+pub fn compute_serialized_packed_size<U: MessageBuilder>(message : &U) -> usize {
+    message.get_segments_for_output(|segments| {
+        // The segment table is written, and so packed, separately from the segments.
+        let table = serialize::encode_segment_table(segments);
+        let mut size = compute_packed_size(Word::words_to_bytes(table.as_slice()));
+        for segment in segments.iter() {
+            size += compute_packed_size(Word::words_to_bytes(*segment));
+        }
+        size
+    })
 }
 
 pub fn write_packed_message<T: io::BufferedOutputStream, U: MessageBuilder>(
-    output : &mut T, message : &U) -> ::std::old_io::IoResult<()> {
+    output : &mut T, message : &U) -> ::std::io::Result<()> {
     let mut packed_output_stream = PackedOutputStream {inner : output};
     serialize::write_message(&mut packed_output_stream, message)
 }
 
 
-pub fn write_packed_message_unbuffered<T: ::std::old_io::Writer, U: MessageBuilder>(
-    output : &mut T, message : &U) -> ::std::old_io::IoResult<()> {
+pub fn write_packed_message_unbuffered<T: ::std::io::Write, U: MessageBuilder>(
+    output : &mut T, message : &U) -> ::std::io::Result<()> {
     let mut buffered = io::BufferedOutputStreamWrapper::new(output);
     try!(write_packed_message(&mut buffered, message));
     buffered.flush()
@@ -402,13 +464,13 @@ pub fn write_packed_message_unbuffered<T: ::std::old_io::Writer, U: MessageBuild
 #[cfg(test)]
 mod tests {
     use std;
-    use serialize_packed::{PackedOutputStream, PackedInputStream};
+    use serialize_packed::{PackedOutputStream, PackedInputStream, compute_packed_size};
     use io;
 
     pub fn expect_packs_to(unpacked : &[u8],
                            packed : &[u8]) {
 
-        use std::old_io::{Reader, Writer};
+        use std::io::Write;
 
         // --------
         // write
@@ -423,6 +485,7 @@ mod tests {
 
         assert!(bytes.as_slice().eq(packed),
                 "expected: {:?}, got: {:?}", packed, bytes);
+        assert_eq!(compute_packed_size(unpacked), packed.len());
 
         // --------
         // read
@@ -430,10 +493,11 @@ mod tests {
         let mut reader = io::ArrayInputStream::new(packed);
         let mut packed_input_stream = PackedInputStream {inner : &mut reader};
 
-        let bytes = packed_input_stream.read_exact(unpacked.len()).unwrap();
+        let mut bytes : std::vec::Vec<u8> = ::std::iter::repeat(0u8).take(unpacked.len()).collect();
+        io::read_at_least(&mut packed_input_stream, bytes.as_mut_slice(), unpacked.len()).unwrap();
 
         //    assert!(packed_input_stream.eof());
-        assert!(bytes[].eq(unpacked),
+        assert!(bytes.as_slice().eq(unpacked),
                 "expected: {:?}, got: {:?}", unpacked, bytes);
 
     }
@@ -467,4 +531,25 @@ mod tests {
         expect_packs_to(&[0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0], &[0,2]);
 
     }
+
+    #[test]
+    fn packed_message_size() {
+        use message::{AllocationStrategy, BuilderOptions, MallocMessageBuilder, MessageBuilder};
+        use serialize_packed::{compute_serialized_packed_size, write_packed_message_unbuffered};
+        use text_list;
+
+        let mut options = BuilderOptions::new();
+        options.first_segment_words(4).allocation_strategy(AllocationStrategy::FixedSize);
+        let mut message = MallocMessageBuilder::new(options).unwrap();
+        {
+            let mut list = message.get_root_internal().unwrap().init_as_sized::<text_list::Builder>(3).unwrap();
+            list.set(0, "").unwrap();
+            list.set(1, "a string that fills several words with nonzero bytes").unwrap();
+            list.set(2, "x").unwrap();
+        }
+
+        let mut bytes : Vec<u8> = Vec::new();
+        write_packed_message_unbuffered(&mut bytes, &message).unwrap();
+        assert_eq!(compute_serialized_packed_size(&message), bytes.len());
+    }
 }
@@ -25,6 +25,20 @@ use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder, In
 use traits::{FromPointerReader, FromPointerBuilder,
              FromStructBuilder, FromStructReader, HasStructSize,
              IndexMove, ListIter};
+use traits;
+use Result;
+
+/// `T` is the `Owned` marker of the element struct.
+pub struct Owned<T>;
+
+impl <T> Copy for Owned<T> {}
+
+impl <'a, T : traits::Owned<'a>> traits::Owned<'a> for Owned<T>
+    where <T as traits::Owned<'a>>::Reader : FromStructReader<'a>,
+          <T as traits::Owned<'a>>::Builder : FromStructBuilder<'a> + HasStructSize {
+    type Reader = Reader<'a, <T as traits::Owned<'a>>::Reader>;
+    type Builder = Builder<'a, <T as traits::Owned<'a>>::Builder>;
+}
 
 pub struct Reader<'a, T> {
     reader : ListReader<'a>
@@ -52,8 +66,8 @@ impl <'a, T> Reader<'a, T>  {
 
 
 impl <'a, T : FromStructReader<'a>> FromPointerReader<'a> for Reader<'a, T> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a, T> {
-        Reader { reader : reader.get_list(InlineComposite, ::std::ptr::null()) }
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
+        Ok(Reader { reader : try!(reader.get_list(InlineComposite, ::std::ptr::null())) })
     }
 }
 
@@ -94,15 +108,15 @@ impl <'a, T> Builder<'a, T> {
 }
 
 impl <'a, T : FromStructBuilder<'a> + HasStructSize> FromPointerBuilder<'a> for Builder<'a, T> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a, T> {
-        Builder {
-            builder : builder.init_struct_list(size, HasStructSize::struct_size(None::<T>))
-        }
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a, T>> {
+        Ok(Builder {
+            builder : try!(builder.init_struct_list(size, HasStructSize::struct_size(None::<T>)))
+        })
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a, T> {
-        Builder {
-            builder : builder.get_struct_list(HasStructSize::struct_size(None::<T>), ::std::ptr::null())
-        }
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a, T>> {
+        Ok(Builder {
+            builder : try!(builder.get_struct_list(HasStructSize::struct_size(None::<T>), ::std::ptr::null()))
+        })
     }
 }
 
@@ -117,8 +131,9 @@ impl <'a, T : FromStructBuilder<'a>> Builder<'a, T> {
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a, T>) {
-        pointer.set_list(&value.reader);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a, T>) -> Result<()> {
+        pointer.set_list(&value.reader)
     }
 }
 
@@ -33,8 +33,16 @@ pub fn new_reader<'a>(p : *const u8, len : u32) -> Result<Reader<'a>, ::std::str
     ::std::str::from_utf8(v)
 }
 
+#[derive(Copy)]
+pub struct Owned;
+
+impl <'a> ::traits::Owned<'a> for Owned {
+    type Reader = Reader<'a>;
+    type Builder = Builder<'a>;
+}
+
 impl <'a> ::traits::FromPointerReader<'a> for Reader<'a> {
-    fn get_from_pointer(reader : &::private::layout::PointerReader<'a>) -> Reader<'a> {
+    fn get_from_pointer(reader : &::private::layout::PointerReader<'a>) -> ::Result<Reader<'a>> {
         reader.get_text(::std::ptr::null(), 0)
     }
 }
@@ -72,16 +80,17 @@ impl <'a> ::std::str::Str for Builder<'a> {
 }
 
 impl <'a> ::traits::FromPointerBuilder<'a> for Builder<'a> {
-    fn init_pointer(builder : ::private::layout::PointerBuilder<'a>, size : u32) -> Builder<'a> {
+    fn init_pointer(builder : ::private::layout::PointerBuilder<'a>, size : u32) -> ::Result<Builder<'a>> {
         builder.init_text(size)
     }
-    fn get_from_pointer(builder : ::private::layout::PointerBuilder<'a>) -> Builder<'a> {
+    fn get_from_pointer(builder : ::private::layout::PointerBuilder<'a>) -> ::Result<Builder<'a>> {
         builder.get_text(::std::ptr::null(), 0)
     }
 }
 
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a>) {
-        pointer.set_text(value);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a>) -> ::Result<()> {
+        pointer.set_text(value)
     }
 }
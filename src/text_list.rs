@@ -22,7 +22,17 @@
 //! List of strings containing UTF-8 encoded text.
 
 use traits::{FromPointerReader, FromPointerBuilder};
+use traits;
 use private::layout::{ListBuilder, ListReader, Pointer, PointerBuilder, PointerReader};
+use Result;
+
+#[derive(Copy)]
+pub struct Owned;
+
+impl <'a> traits::Owned<'a> for Owned {
+    type Reader = Reader<'a>;
+    type Builder = Builder<'a>;
+}
 
 #[derive(Copy)]
 pub struct Reader<'a> {
@@ -38,13 +48,13 @@ impl <'a> Reader<'a> {
 }
 
 impl <'a> FromPointerReader<'a> for Reader<'a> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Reader<'a> {
-        Reader { reader : reader.get_list(Pointer, ::std::ptr::null()) }
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a>> {
+        Ok(Reader { reader : try!(reader.get_list(Pointer, ::std::ptr::null())) })
     }
 }
 
 impl <'a> Reader<'a> {
-    pub fn get(self, index : u32) -> ::text::Reader<'a> {
+    pub fn get(self, index : u32) -> Result<::text::Reader<'a>> {
         assert!(index <  self.len());
         self.reader.get_pointer_element(index).get_text(::std::ptr::null(), 0)
     }
@@ -61,9 +71,9 @@ impl <'a> Builder<'a> {
 
     pub fn len(&self) -> u32 { self.builder.len() }
 
-    pub fn set(&mut self, index : u32, value : ::text::Reader) {
+    pub fn set(&mut self, index : u32, value : ::text::Reader) -> Result<()> {
         assert!(index < self.len());
-        self.builder.get_pointer_element(index).set_text(value);
+        self.builder.get_pointer_element(index).set_text(value)
     }
 
     pub fn borrow<'b>(&'b mut self) -> Builder<'b> {
@@ -73,27 +83,28 @@ impl <'a> Builder<'a> {
 
 
 impl <'a> FromPointerBuilder<'a> for Builder<'a> {
-    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a> {
-        Builder {
-            builder : builder.init_list(Pointer, size)
-        }
+    fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Result<Builder<'a>> {
+        Ok(Builder {
+            builder : try!(builder.init_list(Pointer, size))
+        })
     }
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Builder<'a> {
-        Builder {
-            builder : builder.get_list(Pointer, ::std::ptr::null())
-        }
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a>> {
+        Ok(Builder {
+            builder : try!(builder.get_list(Pointer, ::std::ptr::null()))
+        })
     }
 }
 
 impl <'a> Builder<'a> {
-    pub fn get(self, index : u32) -> ::text::Builder<'a> {
+    pub fn get(self, index : u32) -> Result<::text::Builder<'a>> {
         self.builder.get_pointer_element(index).get_text(::std::ptr::null(), 0)
     }
 }
 
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
-    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a>) {
-        pointer.set_list(&value.reader);
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : Reader<'a>) -> Result<()> {
+        pointer.set_list(&value.reader)
     }
 }
 
@@ -19,7 +19,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use Word;
+use {Result, Word};
 use private::layout::{StructReader, StructBuilder, StructSize, PointerBuilder, PointerReader};
 
 pub trait FromStructReader<'a> {
@@ -35,24 +35,31 @@ pub trait FromStructBuilder<'a> {
 }
 
 pub trait FromPointerReader<'a> {
-    fn get_from_pointer(reader : &PointerReader<'a>) -> Self;
+    fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Self>;
 }
 
 pub trait FromPointerReaderRefDefault<'a> {
-    fn get_from_pointer(reader : &PointerReader<'a>, default_value : *const Word) -> Self;
+    fn get_from_pointer(reader : &PointerReader<'a>, default_value : *const Word) -> Result<Self>;
 }
 
 pub trait FromPointerBuilder<'a> {
-    fn init_pointer(PointerBuilder<'a>, u32) -> Self;
-    fn get_from_pointer(builder : PointerBuilder<'a>) -> Self;
+    fn init_pointer(PointerBuilder<'a>, u32) -> Result<Self>;
+    fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Self>;
 }
 
 pub trait FromPointerBuilderRefDefault<'a> {
-    fn get_from_pointer(builder : PointerBuilder<'a>, default_value : *const Word) -> Self;
+    fn get_from_pointer(builder : PointerBuilder<'a>, default_value : *const Word) -> Result<Self>;
+}
+
+/// Names the reader and builder types of a pointer type independently of any lifetime, so that
+/// the type can be used as a parameter of an owned value such as `message::TypedBuilder<T>`.
+pub trait Owned<'a> {
+    type Reader : FromPointerReader<'a>;
+    type Builder : FromPointerBuilder<'a>;
 }
 
 pub trait SetPointerBuilder<To> {
-    fn set_pointer_builder<'a>(PointerBuilder<'a>, Self);
+    fn set_pointer_builder<'a>(PointerBuilder<'a>, Self) -> Result<()>;
 }
 
 pub trait HasTypeId {
@@ -0,0 +1,94 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Up-front checking of whole messages.
+//!
+//! Messages are normally validated lazily, as each pointer is followed. `MessageReader::validate()`
+//! instead walks every pointer in a message and collects every problem it finds, which is useful
+//! for rejecting bad input at the edge of a system with a helpful diagnostic.
+//!
+//! The wire format does not distinguish `Text` from `Data`, so the walk can only tell which
+//! pointers should be text if the caller says so. `MessageReader::validate_with_text()` takes
+//! the paths of the text fields and checks that each one is NUL-terminated text.
+
+/// One step on the path from the root pointer to an object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathElement {
+    /// A pointer in the pointer section of a struct.
+    PointerField(u16),
+
+    /// An element of a list.
+    ListElement(u32),
+}
+
+/// One step of a path that declares where text is expected. Like a `PathElement`, except that
+/// it can match every element of a list.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathPattern {
+    PointerField(u16),
+    ListElement(u32),
+
+    /// Any element of a list.
+    AnyListElement,
+}
+
+impl PathPattern {
+    pub fn matches(&self, element : &PathElement) -> bool {
+        match (*self, *element) {
+            (PathPattern::PointerField(a), PathElement::PointerField(b)) => a == b,
+            (PathPattern::ListElement(a), PathElement::ListElement(b)) => a == b,
+            (PathPattern::AnyListElement, PathElement::ListElement(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Checks whether `path` is matched step by step by `pattern`.
+pub fn path_matches(pattern : &[PathPattern], path : &[PathElement]) -> bool {
+    pattern.len() == path.len() &&
+        pattern.iter().zip(path.iter()).all(|(p, e)| p.matches(e))
+}
+
+/// Something wrong with a message.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// Where the offending pointer lives, starting from the root.
+    pub path : Vec<PathElement>,
+
+    pub description : &'static str,
+    pub detail : Option<String>,
+}
+
+impl ::std::fmt::Display for Problem {
+    fn fmt(&self, fmt : &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        try!(write!(fmt, "root"));
+        for element in self.path.iter() {
+            match *element {
+                PathElement::PointerField(index) => try!(write!(fmt, ".pointer[{}]", index)),
+                PathElement::ListElement(index) => try!(write!(fmt, "[{}]", index)),
+            }
+        }
+        match self.detail {
+            Some(ref detail) => write!(fmt, ": {} - {}", self.description, detail),
+            None => write!(fmt, ": {}", self.description),
+        }
+    }
+}